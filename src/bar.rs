@@ -0,0 +1,196 @@
+use crate::cli::toggle;
+use crate::config;
+use crate::tailscale::localapi::LocalApi;
+use crate::tailscale::status::Status;
+use crate::tailscale::types::{BackendState, Machine};
+use crate::tailscale::watcher::IpnWatcher;
+use clap::ValueEnum;
+use log::{error, warn};
+use serde::Serialize;
+use std::{
+    io::{self, BufRead},
+    thread,
+};
+
+/// The status bars we know how to feed.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarFormat {
+    /// JSON for a waybar custom module with `return-type` set to `json`
+    Waybar,
+    /// Text with a click action for a polybar `custom/script` module with
+    /// `tail = true`
+    Polybar,
+    /// Text for an i3blocks block with `interval=persist`
+    I3blocks,
+}
+
+/// One line of waybar's JSON protocol.
+#[derive(Serialize, Debug)]
+struct WaybarLine<'a> {
+    text: &'a str,
+    tooltip: &'a str,
+    class: &'a str,
+    alt: &'a str,
+}
+
+/// What the bar shows, whatever the format.
+#[derive(Debug)]
+struct BarState {
+    text: String,
+    tooltip: String,
+    /// A stable name for the state, for styling and icons.
+    class: &'static str,
+}
+
+impl BarState {
+    fn current() -> Self {
+        match Status::get_current() {
+            Ok(ctx) => Self::of(&ctx.status),
+            Err(e) => Self {
+                text: "Unavailable".into(),
+                tooltip: format!("Tailscale: {e}"),
+                class: "unavailable",
+            },
+        }
+    }
+
+    fn of(status: &Status) -> Self {
+        let exit_node = status.peers.values().find(|peer| peer.exit_node);
+        let class = match status.backend_state() {
+            BackendState::Running if exit_node.is_some() => "exit-node",
+            BackendState::Running => "connected",
+            BackendState::Starting => "connecting",
+            BackendState::NeedsLogin => "logged-out",
+            BackendState::NeedsMachineAuth => "needs-approval",
+            BackendState::NoState | BackendState::Stopped => "disconnected",
+        };
+
+        let label = status.backend_state().label();
+        let text = match exit_node {
+            Some(exit_node) => format!("{label} via {}", exit_node.display_name),
+            None => label.to_string(),
+        };
+
+        let mut tooltip = vec![format!("Tailscale: {label}")];
+        if let Some(ip) = status.this_machine.ips.first() {
+            tooltip.push(format!(
+                "This device: {} ({ip})",
+                status.this_machine.display_name
+            ));
+        }
+        if let Some(exit_node) = exit_node {
+            tooltip.push(format!("Exit node: {}", exit_node.display_name));
+        }
+
+        let peers = config::get().peers.clone();
+        let mut shown: Vec<&Machine> = status
+            .peers
+            .values()
+            .filter(|peer| peer.online || !peers.hide_offline)
+            .filter(|peer| !peers.hidden.contains(&peer.display_name.to_string()))
+            .collect();
+        shown.sort_by_key(|peer| (!peer.online, peer.display_name.to_string()));
+        if !shown.is_empty() {
+            tooltip.push("Peers:".into());
+        }
+        for peer in shown {
+            let ip = peer
+                .ips
+                .first()
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            tooltip.push(format!(
+                "  {}  {ip}  {}",
+                peer.display_name,
+                peer.presence()
+            ));
+        }
+
+        Self {
+            text,
+            tooltip: tooltip.join("\n"),
+            class,
+        }
+    }
+}
+
+impl BarFormat {
+    fn render(self, state: &BarState) -> String {
+        match self {
+            // waybar reads both as Pango markup
+            Self::Waybar => {
+                let text = escape_markup(&state.text);
+                let tooltip = escape_markup(&state.tooltip);
+                let line = WaybarLine {
+                    text: &text,
+                    tooltip: &tooltip,
+                    class: state.class,
+                    alt: state.class,
+                };
+                serde_json::to_string(&line).unwrap_or_default()
+            }
+            Self::Polybar => {
+                let exe = std::env::current_exe()
+                    .map(|exe| exe.display().to_string())
+                    .unwrap_or_else(|_| "tailray".into());
+                format!(
+                    "%{{A1:{} toggle:}}{}%{{A}}",
+                    exe.replace(':', "\\:"),
+                    state.text.replace('%', "%%")
+                )
+            }
+            Self::I3blocks => state.text.clone(),
+        }
+    }
+}
+
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// i3blocks writes clicks on persistent blocks to our stdin, either as the
+// button number or, with `format=json`, as a JSON object.
+fn spawn_click_reader() {
+    #[derive(serde::Deserialize)]
+    struct Click {
+        button: u8,
+    }
+
+    thread::spawn(|| {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            let button = line.trim().parse().ok().or_else(|| {
+                serde_json::from_str::<Click>(&line)
+                    .ok()
+                    .map(|click| click.button)
+            });
+            if button != Some(1) {
+                continue;
+            }
+            if let Err(e) = toggle() {
+                error!("failed to toggle the connection: {e}");
+            }
+        }
+        warn!("stdin closed, no longer handling clicks");
+    });
+}
+
+/// Prints a line for the status bar every time the status changes, forever.
+pub fn run(format: BarFormat) -> ! {
+    if format == BarFormat::I3blocks {
+        spawn_click_reader();
+    }
+
+    let mut last = None;
+    IpnWatcher::new(LocalApi::default()).run(|| {
+        let line = format.render(&BarState::current());
+        if last.as_ref() != Some(&line) {
+            println!("{line}");
+            last = Some(line);
+        }
+    })
+}
@@ -0,0 +1,78 @@
+//! Runs a whitelisted `tailscale` command as root on behalf of Tailray.
+//!
+//! pkexec picks the polkit action from the first argument (see
+//! `polkit/org.tailray.policy.in`), so each action can only ever run the
+//! commands it is named after:
+//!
+//! ```text
+//! tailray-helper up
+//! tailray-helper down
+//! tailray-helper exit-node <node ID, IP or name, or empty to clear it>
+//! tailray-helper set-pref <pref> <true|false>
+//! ```
+
+#[path = "../helper_prefs.rs"]
+mod helper_prefs;
+
+use helper_prefs::HELPER_PREFS;
+use std::{
+    env,
+    os::unix::process::CommandExt,
+    path::Path,
+    process::{exit, Command},
+};
+
+// pkexec resets PATH, so look in the usual places ourselves
+const TAILSCALE_PATHS: [&str; 4] = [
+    "/usr/bin/tailscale",
+    "/usr/sbin/tailscale",
+    "/usr/local/bin/tailscale",
+    "/run/current-system/sw/bin/tailscale",
+];
+
+// node IDs, IPs and MagicDNS names, nothing that could pass for a flag
+fn is_exit_node(node: &str) -> bool {
+    !node.starts_with('-')
+        && node
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | '-' | '_'))
+}
+
+fn tailscale_args(args: &[String]) -> Option<Vec<String>> {
+    match args {
+        [verb] if verb == "up" || verb == "down" => Some(vec![verb.clone()]),
+        [action, node] if action == "exit-node" && is_exit_node(node) => {
+            Some(vec!["set".into(), format!("--exit-node={node}")])
+        }
+        [action, pref, value]
+            if action == "set-pref"
+                && HELPER_PREFS.contains(&pref.as_str())
+                && (value == "true" || value == "false") =>
+        {
+            Some(vec!["set".into(), format!("--{pref}={value}")])
+        }
+        _ => None,
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some(tailscale_args) = tailscale_args(&args) else {
+        eprintln!("tailray-helper: refusing to run tailscale with {args:?}");
+        exit(2);
+    };
+
+    let Some(tailscale) = TAILSCALE_PATHS
+        .into_iter()
+        .map(Path::new)
+        .find(|path| path.exists())
+    else {
+        eprintln!("tailray-helper: tailscale not found in {TAILSCALE_PATHS:?}");
+        exit(127);
+    };
+
+    // only returns if the exec failed
+    let e = Command::new(tailscale).args(tailscale_args).exec();
+    eprintln!("tailray-helper: failed to run {}: {e}", tailscale.display());
+    exit(126);
+}
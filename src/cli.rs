@@ -0,0 +1,182 @@
+use crate::bar::{self, BarFormat};
+use crate::clipboard;
+use crate::control::{self, ControlError, Request};
+use crate::tailscale::export::StatusExport;
+use crate::tailscale::link::set_link;
+use crate::tailscale::peer::copy_peer_ip;
+use crate::tailscale::status::Status;
+use crate::tailscale::types::Machine;
+use clap::{Parser, Subcommand};
+use log::LevelFilter;
+use std::{error::Error, path::PathBuf};
+
+/// A system tray for Tailscale.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    /// Only log messages at this level or above (off, error, warn, info,
+    /// debug or trace)
+    #[arg(long, value_name = "LEVEL", default_value = "info")]
+    pub log_level: LevelFilter,
+
+    /// Path to tailscaled's LocalAPI socket
+    #[arg(long, value_name = "PATH", env = "TAILRAY_SOCKET")]
+    pub socket: Option<PathBuf>,
+
+    /// Seconds between status checks while the IPN bus is unavailable, and
+    /// between exit node checks [default: from the config file, or 30]
+    #[arg(long, value_name = "SECONDS")]
+    pub interval: Option<u64>,
+
+    /// Path to the configuration file [default:
+    /// ~/.config/tailray/config.toml]
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print a summary of the Tailscale status
+    Status {
+        /// Print JSON in Tailray's documented schema instead
+        #[arg(long)]
+        json: bool,
+    },
+    /// Connect to Tailscale
+    Up,
+    /// Disconnect from Tailscale
+    Down,
+    /// Connect if disconnected, disconnect otherwise
+    Toggle,
+    /// Make the running tray reload the Tailscale status
+    Refresh,
+    /// Quit the running tray
+    Quit,
+    /// Copy the IP address of a peer, or this device, to the clipboard
+    CopyIp {
+        /// Name of the peer, as shown in the tray
+        peer: String,
+    },
+    /// Keep printing the status for a status bar
+    Bar {
+        #[arg(long, value_enum)]
+        format: BarFormat,
+    },
+}
+
+impl Command {
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Self::Status { json: false } => print_status(),
+            Self::Status { json: true } => print_status_json(),
+            Self::Up => link(true),
+            Self::Down => link(false),
+            Self::Toggle => toggle(),
+            Self::Refresh => forward(Request::Refresh),
+            Self::Quit => forward(Request::Quit),
+            Self::CopyIp { peer } => copy_ip(peer),
+            Self::Bar { format } => bar::run(*format),
+        }
+    }
+}
+
+fn forward(request: Request) -> Result<(), Box<dyn Error>> {
+    control::send(request)?;
+    Ok(())
+}
+
+// The running tray notifies and updates its menu itself, so let it do the
+// work if there is one.
+fn link(up: bool) -> Result<(), Box<dyn Error>> {
+    let request = if up { Request::Up } else { Request::Down };
+    match control::send(request) {
+        Err(ControlError::NotRunning) => Ok(set_link(up)?),
+        result => {
+            result?;
+            Ok(())
+        }
+    }
+}
+
+pub fn toggle() -> Result<(), Box<dyn Error>> {
+    let ctx = Status::get_current()?;
+    link(!ctx.status.is_up())
+}
+
+fn ip(machine: &Machine) -> String {
+    machine
+        .ips
+        .first()
+        .map(ToString::to_string)
+        .unwrap_or_default()
+}
+
+fn print_status() -> Result<(), Box<dyn Error>> {
+    let ctx = Status::get_current()?;
+    let status = &ctx.status;
+
+    println!("Tailscale: {}", status.backend_state().label());
+    println!(
+        "This device: {} ({})",
+        status.this_machine.display_name, ctx.ip
+    );
+    if let Some(exit_node) = status.peers.values().find(|peer| peer.exit_node) {
+        println!("Exit node: {} ({})", exit_node.display_name, ip(exit_node));
+    }
+
+    let mut peers: Vec<&Machine> = status.peers.values().collect();
+    peers.sort_by_key(|peer| (!peer.online, peer.display_name.to_string()));
+    let online = peers.iter().filter(|peer| peer.online).count();
+    println!("Peers: {online} of {} online", peers.len());
+    for peer in peers {
+        println!(
+            "  {:<24} {:<16} {}",
+            peer.display_name.to_string(),
+            ip(peer),
+            peer.presence()
+        );
+    }
+
+    Ok(())
+}
+
+fn print_status_json() -> Result<(), Box<dyn Error>> {
+    let ctx = Status::get_current()?;
+    let export = StatusExport::of(&ctx.status);
+    println!("{}", serde_json::to_string_pretty(&export)?);
+    Ok(())
+}
+
+fn copy_ip(name: &str) -> Result<(), Box<dyn Error>> {
+    // the running tray can hold on to the clipboard for us
+    match control::send(Request::CopyIp(name.to_string())) {
+        Ok(ip) => {
+            println!("{ip}");
+            return Ok(());
+        }
+        Err(ControlError::NotRunning) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let ctx = Status::get_current()?;
+    let status = &ctx.status;
+
+    let (machine, host) = status
+        .find_machine(name)
+        .ok_or_else(|| format!("no peer named {name}"))?;
+
+    let ip = ip(machine);
+    if ip.is_empty() {
+        return Err(format!("{name} has no Tailscale IP address").into());
+    }
+    copy_peer_ip(&ip, &format!("{} ({ip})", machine.display_name), host)?;
+    println!("{ip}");
+
+    // the clipboard is only ours for as long as we keep running
+    eprintln!("Keeping {ip} on the clipboard until something else is copied.");
+    clipboard::hold(&ip)?;
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use arboard::Clipboard;
+use arboard::{Clipboard, SetExtLinux};
 
 pub fn copy(text: &str) -> Result<(), arboard::Error> {
     let mut clipboard = Clipboard::new()?;
@@ -9,3 +9,10 @@ pub fn get() -> Result<String, arboard::Error> {
     let mut clipboard = Clipboard::new()?;
     clipboard.get_text()
 }
+
+/// Keeps serving `text` until another program takes over the clipboard, so
+/// it outlives short-lived processes like `tailray copy-ip`.
+pub fn hold(text: &str) -> Result<(), arboard::Error> {
+    let mut clipboard = Clipboard::new()?;
+    clipboard.set().wait().text(text)
+}
@@ -0,0 +1,449 @@
+use inotify::{Inotify, WatchMask};
+use log::{error, info, warn};
+use notify_rust::Notification;
+use serde::{de::IntoDeserializer, Deserialize, Deserializer};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock, RwLock},
+    thread,
+    time::Duration,
+};
+use thiserror::Error;
+
+const DEFAULT_ADMIN_URL: &str = "https://login.tailscale.com/admin/machines";
+
+static CONFIG: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+static SOURCE: OnceLock<(PathBuf, Overrides)> = OnceLock::new();
+
+type Result<T> = std::result::Result<T, ConfigError>;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read {}", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("{}, line {line}: {message}", path.display())]
+    Invalid {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+
+    #[error("failed to watch {} for changes", path.display())]
+    Watch {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IconTheme {
+    /// Prefer the icon theme's Tailscale icons, falling back to our own.
+    System,
+    /// Always draw our own icons.
+    Builtin,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Escalation {
+    /// The first of pkexec, run0, sudo and doas that is installed.
+    Auto,
+    Pkexec,
+    Run0,
+    Sudo,
+    Doas,
+}
+
+impl Escalation {
+    // TAILRAY_ESCALATION predates the config file
+    fn from_env() -> Self {
+        let name = std::env::var("TAILRAY_ESCALATION").unwrap_or_default();
+        if name.is_empty() {
+            return Self::Auto;
+        }
+        Self::deserialize(name.as_str().into_deserializer()).unwrap_or_else(
+            |e: serde::de::value::Error| {
+                warn!("Ignoring TAILRAY_ESCALATION: {e}");
+                Self::Auto
+            },
+        )
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerSort {
+    /// Online peers first, then by name.
+    Online,
+    Name,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeersConfig {
+    pub sort: PeerSort,
+    pub hide_offline: bool,
+    /// Peers never to list, by name.
+    pub hidden: Vec<String>,
+}
+
+impl Default for PeersConfig {
+    fn default() -> Self {
+        Self {
+            sort: PeerSort::Online,
+            hide_offline: false,
+            hidden: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationsConfig {
+    pub connection: bool,
+    pub clipboard: bool,
+    pub exit_node: bool,
+    pub taildrop: bool,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self {
+            connection: true,
+            clipboard: true,
+            exit_node: true,
+            taildrop: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SshConfig {
+    /// Terminal command the SSH command is appended to.
+    pub terminal: String,
+    pub command: String,
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        let terminal = std::env::var("TERMINAL").unwrap_or_else(|_| "x-terminal-emulator".into());
+        Self {
+            terminal: format!("{terminal} -e"),
+            command: "ssh".into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TaildropConfig {
+    #[serde(deserialize_with = "expand_home")]
+    pub directory: PathBuf,
+    pub auto_accept: bool,
+}
+
+impl Default for TaildropConfig {
+    fn default() -> Self {
+        let directory = std::env::var_os("TAILRAY_TAILDROP_DIR")
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join("Downloads")))
+            .unwrap_or_else(|| PathBuf::from("."));
+        Self {
+            directory,
+            auto_accept: true,
+        }
+    }
+}
+
+/// Everything configurable in `config.toml`. Missing keys keep their
+/// defaults, which honour the older `TAILRAY_*` environment variables.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub admin_url: String,
+    /// Seconds between status checks while the IPN bus is unavailable, and
+    /// between exit node checks.
+    pub poll_interval: u64,
+    pub escalation: Escalation,
+    pub icon_theme: IconTheme,
+    pub peers: PeersConfig,
+    pub notifications: NotificationsConfig,
+    pub ssh: SshConfig,
+    pub taildrop: TaildropConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            admin_url: std::env::var("TAILRAY_ADMIN_URL")
+                .unwrap_or_else(|_| DEFAULT_ADMIN_URL.into()),
+            poll_interval: 30,
+            escalation: Escalation::from_env(),
+            icon_theme: IconTheme::System,
+            peers: PeersConfig::default(),
+            notifications: NotificationsConfig::default(),
+            ssh: SshConfig::default(),
+            taildrop: TaildropConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn poll_interval(&self) -> Duration {
+        // zero would have us poll in a busy loop
+        Duration::from_secs(self.poll_interval.max(1))
+    }
+
+    fn load(path: &Path, overrides: &Overrides) -> Result<Self> {
+        let mut config = match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| ConfigError::Invalid {
+                path: path.to_path_buf(),
+                line: e
+                    .span()
+                    .map_or(1, |span| contents[..span.start].matches('\n').count() + 1),
+                message: e.message().to_string(),
+            })?,
+            // no config file is fine, everything has a default
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(source) => {
+                return Err(ConfigError::Read {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+
+        if let Some(poll_interval) = overrides.poll_interval {
+            config.poll_interval = poll_interval;
+        }
+        Ok(config)
+    }
+}
+
+/// Settings given on the command line, which win over the config file.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pub poll_interval: Option<u64>,
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(PathBuf::from)
+}
+
+fn expand_home<'de, D>(deserializer: D) -> std::result::Result<PathBuf, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let path = PathBuf::deserialize(deserializer)?;
+    Ok(match (path.strip_prefix("~"), home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path,
+    })
+}
+
+/// `$XDG_CONFIG_HOME/tailray/config.toml`, or `~/.config/tailray/config.toml`.
+pub fn default_path() -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".config")))
+        .unwrap_or_default()
+        .join("tailray")
+        .join("config.toml")
+}
+
+/// The current configuration.
+pub fn get() -> Arc<Config> {
+    CONFIG
+        .read()
+        .ok()
+        .and_then(|config| config.clone())
+        .unwrap_or_default()
+}
+
+fn set(config: Config) -> Arc<Config> {
+    let config = Arc::new(config);
+    if let Ok(mut current) = CONFIG.write() {
+        *current = Some(Arc::clone(&config));
+    }
+    config
+}
+
+/// Loads the configuration from `path`. If that fails the defaults are used
+/// until the file is fixed.
+pub fn init(path: PathBuf, overrides: Overrides) -> Result<()> {
+    let loaded = Config::load(&path, &overrides);
+    let _ = SOURCE.set((path, overrides));
+    match loaded {
+        Ok(config) => {
+            set(config);
+            Ok(())
+        }
+        Err(e) => {
+            set(Config::default());
+            Err(e)
+        }
+    }
+}
+
+/// Tells the user their config file is broken, and where.
+pub fn notify_invalid(e: &ConfigError) {
+    let shown = Notification::new()
+        .summary("Invalid Tailray configuration")
+        .body(&e.to_string())
+        .icon("dialog-error")
+        .show();
+    if let Err(e) = shown {
+        error!("failed to show notification: {e}");
+    }
+}
+
+/// Reloads the configuration whenever the file changes, then calls
+/// `on_reload` with the old and new configuration. A broken file is
+/// reported and the last good configuration kept.
+pub fn watch<F>(mut on_reload: F) -> Result<()>
+where
+    F: FnMut(&Config, &Config) + Send + 'static,
+{
+    let Some((path, overrides)) = SOURCE.get() else {
+        return Ok(());
+    };
+    let watch_error = |source| ConfigError::Watch {
+        path: path.clone(),
+        source,
+    };
+
+    // Editors often replace the file rather than write to it, so watch the
+    // directory and pick out our file.
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir).map_err(watch_error)?;
+    let mut inotify = Inotify::init().map_err(watch_error)?;
+    inotify
+        .watches()
+        .add(
+            dir,
+            WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::DELETE,
+        )
+        .map_err(watch_error)?;
+    let file_name = path.file_name().map(ToOwned::to_owned);
+
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(e) => {
+                    error!("stopped watching {} for changes: {e}", path.display());
+                    return;
+                }
+            };
+            if !events
+                .into_iter()
+                .any(|event| event.name == file_name.as_deref())
+            {
+                continue;
+            }
+
+            match Config::load(path, overrides) {
+                Ok(config) if config == *get() => {}
+                Ok(config) => {
+                    info!("reloaded {}", path.display());
+                    let old = get();
+                    let new = set(config);
+                    on_reload(&old, &new);
+                }
+                Err(e) => {
+                    warn!("keeping the previous configuration: {e}");
+                    notify_invalid(&e);
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    // Writes `contents` to a config file of its own, removed when the test
+    // is done with it.
+    struct TempConfig(PathBuf);
+
+    impl TempConfig {
+        fn new(name: &str, contents: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("tailray-config-{}-{name}.toml", process::id()));
+            fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempConfig {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn load(file: &TempConfig) -> Result<Config> {
+        Config::load(&file.0, &Overrides::default())
+    }
+
+    #[test]
+    fn loads_a_valid_file() {
+        let file = TempConfig::new(
+            "valid",
+            "poll_interval = 5\nescalation = \"run0\"\n\n[peers]\nsort = \"name\"\n",
+        );
+        let config = load(&file).unwrap();
+        assert_eq!(config.poll_interval, 5);
+        assert_eq!(config.escalation, Escalation::Run0);
+        assert_eq!(config.peers.sort, PeerSort::Name);
+        assert_eq!(config.notifications, NotificationsConfig::default());
+
+        let overrides = Overrides {
+            poll_interval: Some(60),
+        };
+        let config = Config::load(&file.0, &overrides).unwrap();
+        assert_eq!(config.poll_interval, 60);
+    }
+
+    #[test]
+    fn uses_the_defaults_without_a_file() {
+        let path = std::env::temp_dir().join(format!("tailray-config-{}-none", process::id()));
+        let config = Config::load(&path, &Overrides::default()).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn reports_the_line_of_an_invalid_value() {
+        let file = TempConfig::new(
+            "invalid",
+            "poll_interval = 5\n\n[peers]\nsort = \"alphabetical\"\n",
+        );
+        let Err(ConfigError::Invalid { line, message, .. }) = load(&file) else {
+            panic!("loaded an invalid sort order");
+        };
+        assert_eq!(line, 4);
+        assert!(message.contains("alphabetical"), "{message}");
+    }
+
+    #[test]
+    fn reports_unknown_escalation_backends_when_loading() {
+        let file = TempConfig::new("escalation", "admin_url = \"x\"\nescalation = \"pkexce\"\n");
+        assert!(matches!(
+            load(&file),
+            Err(ConfigError::Invalid { line: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,228 @@
+use crate::dbus_service::DbusService;
+use crate::shutdown::{self, Exit};
+use crate::tray::menu::SysTray;
+use crate::tray::utils::refresh_status;
+use ksni::blocking::Handle;
+use log::{info, warn};
+use std::{
+    fmt, fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    str::FromStr,
+    thread,
+};
+use thiserror::Error;
+use whoami::username;
+
+type Result<T> = std::result::Result<T, ControlError>;
+
+#[derive(Error, Debug)]
+pub enum ControlError {
+    #[error("Tailray is already running")]
+    AlreadyRunning,
+
+    #[error("Tailray is not running")]
+    NotRunning,
+
+    #[error("failed to listen on {}", path.display())]
+    Listen {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to talk to the running Tailray")]
+    Io(#[from] io::Error),
+
+    #[error("the running Tailray failed to {request}: {message}")]
+    Failed { request: Request, message: String },
+
+    #[error("unknown request {0:?}, expected up, down, refresh, quit or copy-ip <peer>")]
+    Unknown(String),
+}
+
+/// What a later invocation can ask the running tray to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Up,
+    Down,
+    Refresh,
+    Quit,
+    /// Copy the IP address of the named peer, or this device, replying with
+    /// the address.
+    CopyIp(String),
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Up => write!(f, "up"),
+            Self::Down => write!(f, "down"),
+            Self::Refresh => write!(f, "refresh"),
+            Self::Quit => write!(f, "quit"),
+            Self::CopyIp(peer) => write!(f, "copy-ip {peer}"),
+        }
+    }
+}
+
+impl FromStr for Request {
+    type Err = ControlError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(' ') {
+            Some(("copy-ip", peer)) if !peer.is_empty() => Ok(Self::CopyIp(peer.to_string())),
+            Some(_) => Err(ControlError::Unknown(s.to_string())),
+            None => match s {
+                "up" => Ok(Self::Up),
+                "down" => Ok(Self::Down),
+                "refresh" => Ok(Self::Refresh),
+                "quit" => Ok(Self::Quit),
+                _ => Err(ControlError::Unknown(s.to_string())),
+            },
+        }
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/tailray.sock`, or a per-user socket in the temporary
+/// directory if there is no runtime directory.
+pub fn socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR").map_or_else(
+        || std::env::temp_dir().join(format!("tailray-{}.sock", username())),
+        |dir| PathBuf::from(dir).join("tailray.sock"),
+    )
+}
+
+/// Binds the control socket, which doubles as the lock making this the only
+/// running tray.
+pub fn claim() -> Result<UnixListener> {
+    let path = socket_path();
+    let listen_error = |source| ControlError::Listen {
+        path: path.clone(),
+        source,
+    };
+
+    match UnixListener::bind(&path) {
+        Ok(listener) => Ok(listener),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(ControlError::AlreadyRunning);
+            }
+            // nobody is listening, so it was left behind by a crashed tray
+            fs::remove_file(&path).map_err(listen_error)?;
+            UnixListener::bind(&path).map_err(listen_error)
+        }
+        Err(source) => Err(listen_error(source)),
+    }
+}
+
+/// Asks the running tray to carry out `request`, failing with
+/// [`ControlError::NotRunning`] if there is none. Returns what the tray
+/// replied with, which is empty for everything but [`Request::CopyIp`].
+pub fn send(request: Request) -> Result<String> {
+    let mut stream = match UnixStream::connect(socket_path()) {
+        Ok(stream) => stream,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Err(ControlError::NotRunning)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    writeln!(stream, "{request}")?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    let reply = reply.trim_end();
+    match reply.strip_prefix("error: ") {
+        Some(message) => Err(ControlError::Failed {
+            request,
+            message: message.to_string(),
+        }),
+        None => Ok(reply.strip_prefix("ok").unwrap_or(reply).trim().to_string()),
+    }
+}
+
+/// Carries out requests from later invocations in the background,
+/// publishing what they change on `service`.
+pub fn serve(listener: UnixListener, handle: Handle<SysTray>, service: Option<DbusService>) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let served = stream.and_then(|stream| serve_client(&stream, &handle, service.as_ref()));
+            if let Err(e) = served {
+                warn!("failed to serve a control request: {e}");
+            }
+        }
+    });
+}
+
+fn serve_client(
+    mut stream: &UnixStream,
+    handle: &Handle<SysTray>,
+    service: Option<&DbusService>,
+) -> io::Result<()> {
+    let mut line = String::new();
+    // a second tray checking whether we are still alive
+    if BufReader::new(stream).read_line(&mut line)? == 0 {
+        return Ok(());
+    }
+
+    let request = match line.trim().parse() {
+        Ok(request) => request,
+        Err(e) => return writeln!(stream, "error: {e}"),
+    };
+    info!("Received {request} from another instance.");
+
+    // the link is changed off the tray's thread, as it may ask for a password
+    let result = match request {
+        Request::Up | Request::Down => SysTray::service_link(request == Request::Up)
+            .and_then(|()| Ok(refresh_status(handle, service)?)),
+        Request::Refresh => refresh_status(handle, service).map_err(Into::into),
+        Request::Quit => {
+            shutdown::request(Exit::Quit);
+            Ok(())
+        }
+        Request::CopyIp(peer) => {
+            return match handle.update(|tray| tray.copy_ip(&peer)) {
+                Some(Ok(ip)) => writeln!(stream, "ok {ip}"),
+                Some(Err(e)) => writeln!(stream, "error: {e}"),
+                None => writeln!(stream, "error: the tray service has stopped"),
+            };
+        }
+    };
+    match result {
+        Ok(()) => writeln!(stream, "ok"),
+        Err(e) => writeln!(stream, "error: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_round_trip_through_their_wire_format() {
+        for request in [
+            Request::Up,
+            Request::Down,
+            Request::Refresh,
+            Request::Quit,
+            Request::CopyIp("laptop".into()),
+        ] {
+            assert_eq!(request.to_string().parse::<Request>().unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_requests() {
+        for line in ["", "restart", "copy-ip", "copy-ip ", "up now"] {
+            assert!(matches!(
+                line.parse::<Request>(),
+                Err(ControlError::Unknown(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,499 @@
+use crate::tray::menu::SysTray;
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    blocking::{stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged, SyncConnection},
+    channel::{MatchingReceiver, Sender},
+    message::{MatchRule, SignalArgs},
+    Message, MethodErr, Path as ObjectPath,
+};
+use dbus_crossroads::Crossroads;
+use ksni::blocking::Handle;
+use log::{error, info};
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use thiserror::Error;
+
+const BUS_NAME: &str = "org.tailray.Tray";
+const INTERFACE: &str = "org.tailray.Tray1";
+const OBJECT_PATH: &str = "/org/tailray/Tray1";
+
+type Result<T> = std::result::Result<T, DbusServiceError>;
+
+#[derive(Error, Debug)]
+pub enum DbusServiceError {
+    #[error("failed to connect to the session bus")]
+    Connect(#[source] dbus::Error),
+
+    #[error("failed to claim {BUS_NAME} on the session bus")]
+    Name(#[source] dbus::Error),
+}
+
+/// What the interface exposes as properties, kept so we only signal
+/// actual changes.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Snapshot {
+    backend_state: String,
+    self_ip: String,
+    exit_node: String,
+    peer_count: u32,
+}
+
+impl Snapshot {
+    fn of(tray: &SysTray) -> Self {
+        let status = &tray.ctx.status;
+        if tray.daemon_error.is_some() {
+            return Self {
+                backend_state: "Unavailable".into(),
+                ..Self::default()
+            };
+        }
+        Self {
+            backend_state: format!("{:?}", status.backend_state()),
+            self_ip: status
+                .this_machine
+                .ips
+                .first()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            exit_node: status
+                .peers
+                .values()
+                .find(|peer| peer.exit_node)
+                .map(|peer| peer.display_name.to_string())
+                .unwrap_or_default(),
+            peer_count: status.peers.len().try_into().unwrap_or(u32::MAX),
+        }
+    }
+
+    fn changes(&self, old: &Self) -> PropMap {
+        let mut changed = PropMap::new();
+        let mut insert = |name: &str, value: Box<dyn RefArg>| {
+            changed.insert(name.to_string(), Variant(value));
+        };
+        if self.backend_state != old.backend_state {
+            insert("BackendState", Box::new(self.backend_state.clone()));
+        }
+        if self.self_ip != old.self_ip {
+            insert("SelfIp", Box::new(self.self_ip.clone()));
+        }
+        if self.exit_node != old.exit_node {
+            insert("ExitNode", Box::new(self.exit_node.clone()));
+        }
+        if self.peer_count != old.peer_count {
+            insert("PeerCount", Box::new(self.peer_count));
+        }
+        changed
+    }
+}
+
+/// Publishes the tray's view of Tailscale on the `org.tailray.Tray1`
+/// interface.
+#[derive(Clone)]
+pub struct DbusService {
+    conn: Arc<SyncConnection>,
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+impl DbusService {
+    /// Claims our name on the session bus and serves the interface in the
+    /// background, acting on the tray behind `handle`.
+    pub fn start(handle: Handle<SysTray>) -> Result<Self> {
+        let conn = SyncConnection::new_session().map_err(DbusServiceError::Connect)?;
+        conn.request_name(BUS_NAME, false, true, true)
+            .map_err(DbusServiceError::Name)?;
+
+        let snapshot = handle.update(|tray| Snapshot::of(tray)).unwrap_or_default();
+        let service = Self {
+            conn: Arc::new(conn),
+            snapshot: Arc::new(Mutex::new(snapshot)),
+        };
+
+        let mut cr = Crossroads::new();
+        let token = cr.register(INTERFACE, |b| {
+            b.signal::<(String,), _>("StatusChanged", ("backend_state",));
+
+            b.property("BackendState")
+                .get(|_, tray: &mut Tray1| Ok(tray.service.snapshot().backend_state));
+            b.property("SelfIp")
+                .get(|_, tray: &mut Tray1| Ok(tray.service.snapshot().self_ip));
+            b.property("ExitNode")
+                .get(|_, tray: &mut Tray1| Ok(tray.service.snapshot().exit_node));
+            b.property("PeerCount")
+                .get(|_, tray: &mut Tray1| Ok(tray.service.snapshot().peer_count));
+
+            b.method("Connect", (), (), |_, tray: &mut Tray1, ()| {
+                tray.act("connect", |tray| tray.do_service_link(true))
+            });
+            b.method("Disconnect", (), (), |_, tray: &mut Tray1, ()| {
+                tray.act("disconnect", |tray| tray.do_service_link(false))
+            });
+            b.method(
+                "SetExitNode",
+                ("name",),
+                (),
+                |_, tray: &mut Tray1, (name,): (String,)| {
+                    tray.act("set the exit node", |tray| tray.set_exit_node(&name))
+                },
+            );
+            b.method(
+                "CopyPeerIp",
+                ("name",),
+                ("ip",),
+                |_, tray: &mut Tray1, (name,): (String,)| {
+                    tray.act("copy the IP address", |tray| tray.copy_ip(&name))
+                        .map(|ip| (ip,))
+                },
+            );
+            b.method("Refresh", (), (), |_, tray: &mut Tray1, ()| {
+                tray.act("refresh", SysTray::update_status)
+            });
+        });
+        cr.insert(
+            OBJECT_PATH,
+            &[token],
+            Tray1 {
+                handle,
+                service: service.clone(),
+            },
+        );
+
+        let cr = Mutex::new(cr);
+        service.conn.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |message, conn| {
+                if let Ok(mut cr) = cr.lock() {
+                    let _ = cr.handle_message(message, conn);
+                }
+                true
+            }),
+        );
+        let conn = Arc::clone(&service.conn);
+        thread::spawn(move || loop {
+            if let Err(e) = conn.process(Duration::from_secs(1)) {
+                error!("stopped serving {INTERFACE}: {e}");
+                return;
+            }
+        });
+
+        info!("Serving {INTERFACE} as {BUS_NAME}.");
+        Ok(service)
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.snapshot
+            .lock()
+            .map(|snapshot| snapshot.clone())
+            .unwrap_or_default()
+    }
+
+    /// Signals whatever changed since the status was last published.
+    pub fn publish(&self, tray: &SysTray) {
+        let new = Snapshot::of(tray);
+        let Ok(mut snapshot) = self.snapshot.lock() else {
+            return;
+        };
+        let changed = new.changes(&snapshot);
+        if changed.is_empty() {
+            return;
+        }
+        *snapshot = new;
+
+        let path = ObjectPath::from(OBJECT_PATH);
+        let properties_changed = PropertiesPropertiesChanged {
+            interface_name: INTERFACE.to_string(),
+            changed_properties: changed,
+            invalidated_properties: Vec::new(),
+        };
+        let status_changed = Message::signal(&path, &INTERFACE.into(), &"StatusChanged".into())
+            .append1(&snapshot.backend_state);
+        for message in [properties_changed.to_emit_message(&path), status_changed] {
+            if self.conn.send(message).is_err() {
+                error!("failed to signal a status change on {INTERFACE}");
+            }
+        }
+    }
+}
+
+/// The object behind `/org/tailray/Tray1`.
+struct Tray1 {
+    handle: Handle<SysTray>,
+    service: DbusService,
+}
+
+impl Tray1 {
+    // Runs a method on the tray, then publishes whatever it changed right
+    // away rather than waiting for the IPN bus to tell us.
+    fn act<T>(
+        &self,
+        what: &str,
+        f: impl FnOnce(&mut SysTray) -> std::result::Result<T, Box<dyn Error>>,
+    ) -> std::result::Result<T, MethodErr> {
+        self.handle
+            .update(|tray| {
+                let result = f(tray);
+                self.service.publish(tray);
+                result.map_err(|e| MethodErr::failed(&format!("failed to {what}: {e}")))
+            })
+            .unwrap_or_else(|| Err(MethodErr::failed(&"the tray service has stopped")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tray::utils::start_tray_service;
+    use dbus::blocking::{stdintf::org_freedesktop_dbus::Properties, Connection};
+    use std::{
+        env, fs,
+        io::{BufRead, BufReader, Write},
+        os::unix::{fs::PermissionsExt, net::UnixListener},
+        path::Path,
+        process::{self, Child, Command, Stdio},
+    };
+
+    const STATUS: &str = r#"{
+        "Version": "1.0",
+        "TUN": true,
+        "BackendState": "Running",
+        "Self": {"ID": "n1", "DNSName": "me.example.ts.net.", "HostName": "me",
+                 "TailscaleIPs": ["100.64.0.1"], "Online": true,
+                 "ExitNode": false, "ExitNodeOption": false},
+        "MagicDNSSuffix": "example.ts.net",
+        "CurrentTailnet": {"Name": "example", "MagicDNSSuffix": "example.ts.net",
+                           "MagicDNSEnabled": true},
+        "Peer": {"key": {"ID": "n2", "DNSName": "peer.example.ts.net.", "HostName": "peer",
+                         "TailscaleIPs": ["100.64.0.2"], "Online": true,
+                         "ExitNode": false, "ExitNodeOption": true}},
+        "User": {}
+    }"#;
+
+    // set for the child process that runs the tray
+    const CHILD_VAR: &str = "TAILRAY_TEST_TRAY1_CLIENT";
+
+    // Stops the bus daemon when the test ends, however it ends.
+    struct Daemon(Child);
+
+    impl Drop for Daemon {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn private_bus() -> Option<(Daemon, String)> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+        Some((Daemon(daemon), address.trim().to_string()))
+    }
+
+    // Stands in for the desktop: a StatusNotifierWatcher for ksni to register
+    // with, and a notification server.
+    fn serve_desktop() {
+        let conn = SyncConnection::new_session().unwrap();
+        for name in [
+            "org.kde.StatusNotifierWatcher",
+            "org.freedesktop.Notifications",
+        ] {
+            conn.request_name(name, false, true, true).unwrap();
+        }
+
+        let mut cr = Crossroads::new();
+        let watcher = cr.register("org.kde.StatusNotifierWatcher", |b| {
+            b.method(
+                "RegisterStatusNotifierItem",
+                ("service",),
+                (),
+                |_, _: &mut (), (_,): (String,)| Ok(()),
+            );
+            b.property("IsStatusNotifierHostRegistered")
+                .get(|_, _: &mut ()| Ok(true));
+            b.property("ProtocolVersion").get(|_, _: &mut ()| Ok(0_i32));
+            b.property("RegisteredStatusNotifierItems")
+                .get(|_, _: &mut ()| Ok(Vec::<String>::new()));
+        });
+        let notifications = cr.register("org.freedesktop.Notifications", |b| {
+            b.method(
+                "Notify",
+                (
+                    "app_name",
+                    "replaces_id",
+                    "app_icon",
+                    "summary",
+                    "body",
+                    "actions",
+                    "hints",
+                    "timeout",
+                ),
+                ("id",),
+                |_,
+                 _: &mut (),
+                 _: (
+                    String,
+                    u32,
+                    String,
+                    String,
+                    String,
+                    Vec<String>,
+                    PropMap,
+                    i32,
+                )| { Ok((1_u32,)) },
+            );
+            b.method(
+                "CloseNotification",
+                ("id",),
+                (),
+                |_, _: &mut (), (_,): (u32,)| Ok(()),
+            );
+        });
+        cr.insert("/StatusNotifierWatcher", &[watcher], ());
+        cr.insert("/org/freedesktop/Notifications", &[notifications], ());
+
+        let cr = Mutex::new(cr);
+        conn.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |message, conn| {
+                let _ = cr.lock().unwrap().handle_message(message, conn);
+                true
+            }),
+        );
+        // until the bus goes away at the end of the test
+        thread::spawn(move || while conn.process(Duration::from_secs(1)).is_ok() {});
+    }
+
+    // Answers the few LocalAPI requests the tray makes, with us as the
+    // operator so that nothing asks for a password.
+    fn serve_localapi(path: &Path) {
+        let _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path).unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+
+                let endpoint = request_line
+                    .split_whitespace()
+                    .nth(1)
+                    .and_then(|path| path.strip_prefix("/localapi/v0/"))
+                    .unwrap_or_default();
+                let (status, body) = match endpoint {
+                    "status" => ("200 OK", STATUS.to_string()),
+                    "prefs" => (
+                        "200 OK",
+                        format!(r#"{{"OperatorUser": "{}"}}"#, whoami::username()),
+                    ),
+                    "profiles/" => ("200 OK", "[]".to_string()),
+                    _ => ("404 Not Found", "not found".to_string()),
+                };
+                let _ = write!(
+                    &stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+    }
+
+    // Records the arguments of every `tailscale` invocation in `log`.
+    fn fake_tailscale(dir: &Path, log: &Path) {
+        fs::create_dir_all(dir).unwrap();
+        let script = dir.join("tailscale");
+        fs::write(
+            &script,
+            format!("#!/bin/sh\necho \"$*\" >> '{}'\n", log.display()),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn serves_tray1_on_a_private_bus() {
+        let Some((_daemon, address)) = private_bus() else {
+            eprintln!("dbus-daemon is not installed, skipping");
+            return;
+        };
+        let scratch = env::temp_dir().join(format!("tailray-dbus-test-{}", process::id()));
+        let bin = scratch.join("bin");
+        let calls = scratch.join("tailscale.log");
+        let socket = scratch.join("tailscaled.sock");
+        fake_tailscale(&bin, &calls);
+        serve_localapi(&socket);
+
+        // ksni and notify-rust only look for the session bus in the
+        // environment, so the tray runs in a child test process that we
+        // hand the bus and our fake `tailscale` to
+        let path = env::var_os("PATH").unwrap_or_default();
+        let mut paths = vec![bin];
+        paths.extend(env::split_paths(&path));
+        let status = Command::new(env::current_exe().unwrap())
+            .args([
+                "dbus_service::tests::tray1_client",
+                "--exact",
+                "--ignored",
+                "--nocapture",
+            ])
+            .env("PATH", env::join_paths(paths).unwrap())
+            .env("DBUS_SESSION_BUS_ADDRESS", &address)
+            .env("TAILRAY_SOCKET", &socket)
+            .env(CHILD_VAR, "1")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(fs::read_to_string(&calls).unwrap(), "up\n");
+
+        let _ = fs::remove_dir_all(scratch);
+    }
+
+    #[test]
+    #[ignore = "run by serves_tray1_on_a_private_bus on the bus it starts"]
+    fn tray1_client() {
+        if env::var_os(CHILD_VAR).is_none() {
+            return;
+        }
+        serve_desktop();
+
+        let handle = start_tray_service().unwrap();
+        let _service = DbusService::start(handle.clone()).unwrap();
+
+        let client = Connection::new_session().unwrap();
+        let tray = client.with_proxy(BUS_NAME, OBJECT_PATH, Duration::from_secs(5));
+        let property = |name: &str| tray.get::<Box<dyn RefArg>>(INTERFACE, name).unwrap();
+        assert_eq!(property("BackendState").as_str(), Some("Running"));
+        assert_eq!(property("PeerCount").as_u64(), Some(1));
+
+        tray.method_call::<(), _, _, _>(INTERFACE, "Connect", ())
+            .unwrap();
+        tray.method_call::<(), _, _, _>(INTERFACE, "Refresh", ())
+            .unwrap();
+
+        let err = tray
+            .method_call::<(String,), _, _, _>(INTERFACE, "CopyPeerIp", ("nobody",))
+            .unwrap_err();
+        assert_eq!(err.name(), Some("org.freedesktop.DBus.Error.Failed"));
+        assert!(err
+            .message()
+            .unwrap_or_default()
+            .contains("no peer named nobody"));
+
+        handle.shutdown().wait();
+    }
+}
@@ -0,0 +1,272 @@
+use log::{error, info};
+use notify_rust::Notification;
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Once,
+};
+use thiserror::Error;
+use which::which;
+use whoami::username;
+
+use crate::config::{self, Escalation};
+use crate::helper_prefs::HELPER_PREFS;
+use crate::tailscale::utils::check_tailscale_operator;
+
+// graphical askpass helpers sudo can use when SUDO_ASKPASS is not set
+const ASKPASS_PROGRAMS: [&str; 4] = [
+    "ksshaskpass",
+    "lxqt-openssh-askpass",
+    "ssh-askpass",
+    "x11-ssh-askpass",
+];
+
+// meson tells us where it installs the helper the polkit policy points at
+const HELPER_PATH: &str = match option_env!("TAILRAY_HELPER_PATH") {
+    Some(path) => path,
+    None => "/usr/libexec/tailray-helper",
+};
+
+type Result<T> = std::result::Result<T, EscalationError>;
+
+#[derive(Error, Debug)]
+pub enum EscalationError {
+    #[error("no privilege escalation tool found, install pkexec, run0, sudo with an askpass program or doas")]
+    Unavailable,
+
+    #[error("{0} is not installed")]
+    NotInstalled(Backend),
+
+    #[error("sudo needs a graphical askpass program, set SUDO_ASKPASS or install ssh-askpass")]
+    NoAskpass,
+}
+
+/// The tools we know how to run `tailscale` as root with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Pkexec,
+    Run0,
+    Sudo,
+    Doas,
+}
+
+impl Backend {
+    // in the order we try them
+    const ALL: [Self; 4] = [Self::Pkexec, Self::Run0, Self::Sudo, Self::Doas];
+
+    fn program(self) -> &'static str {
+        match self {
+            Self::Pkexec => "pkexec",
+            Self::Run0 => "run0",
+            Self::Sudo => "sudo",
+            Self::Doas => "doas",
+        }
+    }
+
+    /// Builds the command that runs `program` through this backend.
+    fn command(self, program: impl AsRef<OsStr>) -> Result<Command> {
+        let path = which(self.program()).map_err(|_| EscalationError::NotInstalled(self))?;
+        let mut command = Command::new(path);
+        match self {
+            Self::Pkexec | Self::Run0 => {}
+            Self::Sudo => {
+                if std::env::var_os("SUDO_ASKPASS").is_none() {
+                    command.env("SUDO_ASKPASS", askpass_path()?);
+                }
+                command.arg("-A");
+            }
+            // doas can't prompt without a terminal, so this only works with
+            // a `nopass` or `persist` rule; fail rather than hang otherwise
+            Self::Doas => {
+                command.arg("-n");
+            }
+        }
+        command.arg(program);
+        Ok(command)
+    }
+
+    fn is_available(self) -> bool {
+        which(self.program()).is_ok()
+            && (self != Self::Sudo
+                || std::env::var_os("SUDO_ASKPASS").is_some()
+                || askpass_path().is_ok())
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.program())
+    }
+}
+
+fn askpass_path() -> Result<PathBuf> {
+    ASKPASS_PROGRAMS
+        .into_iter()
+        .find_map(|program| which(program).ok())
+        .ok_or(EscalationError::NoAskpass)
+}
+
+/// Picks the configured backend, or the first one installed if it is set
+/// to `auto`.
+pub fn backend() -> Result<Backend> {
+    match config::get().escalation {
+        Escalation::Auto => Backend::ALL
+            .into_iter()
+            .find(|backend| backend.is_available())
+            .ok_or(EscalationError::Unavailable),
+        Escalation::Pkexec => Ok(Backend::Pkexec),
+        Escalation::Run0 => Ok(Backend::Run0),
+        Escalation::Sudo => Ok(Backend::Sudo),
+        Escalation::Doas => Ok(Backend::Doas),
+    }
+}
+
+// We don't need to elevate privileges if we're using the Tray service
+// as the root user. This shouldn't really happen, but it's possible
+// depending on how Tailray is ran.
+pub fn should_elevate_perms() -> bool {
+    let parent_user = username();
+
+    if check_tailscale_operator(&parent_user) {
+        return false;
+    }
+
+    if parent_user.eq("root") {
+        return false;
+    }
+
+    true
+}
+
+// Password prompts out of nowhere are confusing, so say once why they happen
+// and how to stop them.
+fn explain_elevation() {
+    static EXPLAINED: Once = Once::new();
+    EXPLAINED.call_once(|| {
+        let shown = Notification::new()
+            .summary("Tailray needs your password")
+            .body(&format!(
+                "{} is not the Tailscale operator, so changing Tailscale settings needs \
+                 administrator rights. Use \"Make me operator\" in Preferences to stop \
+                 these prompts.",
+                username()
+            ))
+            .icon("dialog-password")
+            .show();
+        if let Err(e) = shown {
+            error!("failed to explain the password prompt: {e}");
+        }
+    });
+}
+
+// Every action needing root would fail the same way, so only tell the user
+// the first time.
+fn report_unavailable(e: &EscalationError) {
+    static REPORTED: Once = Once::new();
+    REPORTED.call_once(|| {
+        let shown = Notification::new()
+            .summary("Tailray can't change Tailscale settings")
+            .body(&e.to_string())
+            .icon("dialog-error")
+            .show();
+        if let Err(e) = shown {
+            error!("failed to report the missing escalation tool: {e}");
+        }
+    });
+}
+
+// Maps a `tailscale` invocation to the helper action authorizing it, if
+// there is one.
+fn helper_args(args: &[OsString]) -> Option<Vec<String>> {
+    let args: Vec<&str> = args.iter().map(|arg| arg.to_str()).collect::<Option<_>>()?;
+    match args.as_slice() {
+        [verb @ ("up" | "down")] => Some(vec![verb.to_string()]),
+        ["set", flag] => match flag.strip_prefix("--")?.split_once('=')? {
+            ("exit-node", node) => Some(vec!["exit-node".into(), node.into()]),
+            (pref, value @ ("true" | "false")) if HELPER_PREFS.contains(&pref) => {
+                Some(vec!["set-pref".into(), pref.into(), value.into()])
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn elevated_command(args: &[OsString]) -> Result<Command> {
+    let backend = backend()?;
+
+    // pkexec authorizes each of the helper's actions on its own, which lets
+    // admins allow some of them without a password
+    let helper = Path::new(HELPER_PATH);
+    if let Some(helper_args) = helper_args(args).filter(|_| backend == Backend::Pkexec) {
+        if helper.exists() {
+            info!("Elevating permissions with {}.", helper.display());
+            let mut command = backend.command(helper)?;
+            command.args(helper_args);
+            return Ok(command);
+        }
+    }
+
+    info!("Elevating permissions with {backend}.");
+    let mut command = backend.command("tailscale")?;
+    command.args(args);
+    Ok(command)
+}
+
+/// Builds a command that needs root whoever the Tailscale operator is, such
+/// as starting tailscaled itself.
+pub fn root_command<I, S>(program: &str, args: I) -> Result<Command>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    if username() == "root" {
+        let mut command = Command::new(program);
+        command.args(args);
+        return Ok(command);
+    }
+
+    let command = backend().and_then(|backend| {
+        info!("Elevating permissions with {backend}.");
+        backend.command(program)
+    });
+    match command {
+        Ok(mut command) => {
+            command.args(args);
+            Ok(command)
+        }
+        Err(e) => {
+            error!("can't elevate permissions: {e}");
+            report_unavailable(&e);
+            Err(e)
+        }
+    }
+}
+
+/// Builds a `tailscale` invocation, run through the escalation backend if
+/// we lack the permissions to change tailscaled's state ourselves.
+pub fn tailscale_command<I, S>(args: I) -> Result<Command>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<OsString> = args.into_iter().map(|arg| arg.as_ref().into()).collect();
+    if !should_elevate_perms() {
+        let mut command = Command::new("tailscale");
+        command.args(args);
+        return Ok(command);
+    }
+
+    match elevated_command(&args) {
+        Ok(command) => {
+            explain_elevation();
+            Ok(command)
+        }
+        Err(e) => {
+            error!("can't elevate permissions: {e}");
+            report_unavailable(&e);
+            Err(e)
+        }
+    }
+}
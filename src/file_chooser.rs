@@ -0,0 +1,185 @@
+use crate::shutdown::CommandExt;
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    blocking::Connection,
+    message::MatchRule,
+    Path as ObjectPath,
+};
+use log::{info, warn};
+use std::{
+    io,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use url::Url;
+
+const PORTAL_DEST: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const PORTAL_TIMEOUT: Duration = Duration::from_secs(5);
+// the user gets this long to pick the files before we give up
+const PICK_TIMEOUT: Duration = Duration::from_secs(600);
+
+static REQUEST_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+type Result<T> = std::result::Result<T, FileChooserError>;
+
+#[derive(Error, Debug)]
+pub enum FileChooserError {
+    #[error("no file chooser available (tried the XDG desktop portal, zenity and kdialog)")]
+    Unavailable,
+
+    #[error("file chooser portal request failed")]
+    Portal(#[from] dbus::Error),
+
+    #[error("file chooser timed out")]
+    Timeout,
+
+    #[error("failed to run {program}")]
+    Fallback {
+        program: &'static str,
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// Asks the user to pick one or more files, preferring the XDG desktop
+/// portal and falling back to zenity or kdialog. An empty list means the
+/// user cancelled the dialog.
+pub fn pick_files(title: &str) -> Result<Vec<PathBuf>> {
+    match portal_pick_files(title) {
+        Ok(files) => return Ok(files),
+        Err(FileChooserError::Portal(e)) => {
+            warn!("file chooser portal unavailable, trying fallbacks: {e}");
+        }
+        Err(e) => return Err(e),
+    }
+
+    for (program, args) in [
+        (
+            "zenity",
+            &[
+                "--file-selection",
+                "--multiple",
+                "--separator=\n",
+                "--title",
+            ][..],
+        ),
+        (
+            "kdialog",
+            &[
+                "--getopenfilename",
+                "--multiple",
+                "--separate-output",
+                "--title",
+            ][..],
+        ),
+    ] {
+        let output = match Command::new(program)
+            .args(args)
+            .arg(title)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .cancellable_output()
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(source) => return Err(FileChooserError::Fallback { program, source }),
+        };
+
+        info!("picked files with {program}");
+        // both exit with 1 when the dialog is cancelled
+        if !output.status.success() {
+            return Ok(Vec::new());
+        }
+        return Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect());
+    }
+
+    Err(FileChooserError::Unavailable)
+}
+
+fn portal_pick_files(title: &str) -> Result<Vec<PathBuf>> {
+    let conn = Connection::new_session()?;
+
+    // Subscribe to the response before asking, so we cannot miss it. The
+    // request path is derived from our unique name and the handle token.
+    let token = format!(
+        "tailray{}_{}",
+        std::process::id(),
+        REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let sender = conn.unique_name().trim_start_matches(':').replace('.', "_");
+    let request_path = format!("{PORTAL_PATH}/request/{sender}/{token}");
+
+    let response: Arc<Mutex<Option<(u32, PropMap)>>> = Arc::default();
+    let slot = Arc::clone(&response);
+    let rule = MatchRule::new_signal("org.freedesktop.portal.Request", "Response")
+        .with_path(ObjectPath::from(request_path));
+    conn.add_match(rule, move |reply: (u32, PropMap), _, _| {
+        if let Ok(mut slot) = slot.lock() {
+            *slot = Some(reply);
+        }
+        false
+    })?;
+
+    let mut options = PropMap::new();
+    options.insert(
+        "handle_token".into(),
+        Variant(Box::new(token) as Box<dyn RefArg>),
+    );
+    options.insert(
+        "multiple".into(),
+        Variant(Box::new(true) as Box<dyn RefArg>),
+    );
+
+    let proxy = conn.with_proxy(PORTAL_DEST, PORTAL_PATH, PORTAL_TIMEOUT);
+    let _: (ObjectPath,) = proxy.method_call(
+        "org.freedesktop.portal.FileChooser",
+        "OpenFile",
+        ("", title, options),
+    )?;
+
+    let started = Instant::now();
+    while response.lock().is_ok_and(|response| response.is_none()) {
+        if started.elapsed() > PICK_TIMEOUT {
+            return Err(FileChooserError::Timeout);
+        }
+        conn.process(Duration::from_secs(1))?;
+    }
+
+    let Some((code, results)) = response
+        .lock()
+        .ok()
+        .and_then(|mut response| response.take())
+    else {
+        return Ok(Vec::new());
+    };
+    // 0 is success, 1 cancelled by the user, 2 anything else
+    if code != 0 {
+        info!("file chooser closed without a selection ({code})");
+        return Ok(Vec::new());
+    }
+
+    // the array arrives as a generic RefArg, so walk it instead of casting
+    let files = results
+        .get("uris")
+        .and_then(|uris| uris.0.as_iter())
+        .into_iter()
+        .flatten()
+        .filter_map(|uri| uri.as_str().and_then(file_uri_to_path))
+        .collect();
+    Ok(files)
+}
+
+fn file_uri_to_path(uri: &str) -> Option<PathBuf> {
+    Url::parse(uri).ok()?.to_file_path().ok()
+}
@@ -0,0 +1,11 @@
+//! The boolean `tailscale set` flags covered by the helper's change-prefs
+//! action. Shared with `tailray-helper`, so the tray only offers the helper
+//! prefs it accepts.
+
+pub const HELPER_PREFS: [&str; 5] = [
+    "accept-routes",
+    "accept-dns",
+    "shields-up",
+    "ssh",
+    "exit-node-allow-lan-access",
+];
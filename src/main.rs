@@ -1,43 +1,135 @@
+mod bar;
+mod cli;
 mod clipboard;
-mod pkexec;
+mod config;
+mod control;
+mod dbus_service;
+mod escalation;
+mod file_chooser;
+mod helper_prefs;
+mod session;
+mod shutdown;
 mod svg;
 mod tailscale;
 mod tray;
 
-use log::{error, info, trace};
+use clap::Parser;
+use ksni::blocking::Handle;
+use log::{error, info};
 
-use crate::tailscale::status::Status;
-use crate::tray::utils::start_tray_service;
-use std::process::exit;
+use crate::cli::Cli;
+use crate::config::Overrides;
+use crate::control::ControlError;
+use crate::dbus_service::DbusService;
+use crate::shutdown::{ALREADY_RUNNING, FAILURE};
+use crate::tailscale::localapi::LocalApi;
+use crate::tailscale::watcher::IpnWatcher;
+use crate::tray::menu::SysTray;
+use crate::tray::utils::{
+    refresh_status, report_status_error, spawn_exit_node_monitor, spawn_taildrop_receiver,
+    start_tray_service,
+};
+use std::{error::Error, process::exit};
+
+// Spells out the whole chain of an error, e.g. why tailscaled was unreachable.
+fn describe(e: &dyn Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message = format!("{message}: {cause}");
+        source = cause.source();
+    }
+    message
+}
+
+fn refresh(handle: &Handle<SysTray>, service: Option<&DbusService>) {
+    if let Err(e) = refresh_status(handle, service) {
+        report_status_error(&e);
+    }
+}
 
 fn main() {
+    let cli = Cli::parse();
+
     // initialize logger
-    env_logger::builder()
-        .filter_level(log::LevelFilter::Debug)
-        .init();
+    env_logger::builder().filter_level(cli.log_level).init();
+
+    if let Some(socket) = cli.socket {
+        LocalApi::set_default_socket(socket);
+    }
+    if let Some(config) = &cli.config {
+        if !config.is_file() {
+            error!("Config file {} does not exist", config.display());
+            exit(FAILURE);
+        }
+    }
+    let overrides = Overrides {
+        poll_interval: cli.interval,
+    };
+    let config_result = config::init(cli.config.unwrap_or_else(config::default_path), overrides);
+    if let Err(e) = &config_result {
+        error!("Using the default configuration: {e}");
+    }
+
+    if let Some(command) = cli.command {
+        if let Err(e) = command.run() {
+            error!("{}", describe(e.as_ref()));
+            exit(FAILURE);
+        }
+        return;
+    }
+
+    let listener = match control::claim() {
+        Ok(listener) => listener,
+        Err(ControlError::AlreadyRunning) => {
+            info!("Tailray is already running.");
+            exit(ALREADY_RUNNING);
+        }
+        Err(e) => {
+            error!("{}", describe(&e));
+            exit(FAILURE);
+        }
+    };
 
     // start tray service
     let handle = match start_tray_service() {
         Ok(handle) => handle,
         Err(e) => {
             error!("Failed to start the tray service: {e}");
-            exit(1);
+            exit(FAILURE);
         }
     };
     info!("Tray service started successfully.");
+    if let Err(e) = shutdown::install(handle.clone()) {
+        error!("Signals will not shut the tray down cleanly: {e}");
+    }
 
-    // keep the main thread alive
-    let mut state = false;
-    loop {
-        let ctx = Status::get_current().expect("success");
-        let update_state = ctx.status.is_up();
-        trace!("Tailscale Status = [{}]", update_state);
-        if update_state != state {
-            handle.update(|tray| {
-                tray.ctx = ctx;
-            });
-        }
-        state = update_state;
-        std::thread::sleep(std::time::Duration::from_secs(5));
+    let service = DbusService::start(handle.clone())
+        .inspect_err(|e| error!("{}", describe(e)))
+        .ok();
+    control::serve(listener, handle.clone(), service.clone());
+
+    if let Err(e) = &config_result {
+        config::notify_invalid(e);
     }
+    let reload_handle = handle.clone();
+    let watched = config::watch(move |old, new| {
+        reload_handle.update(|tray| tray.apply_config(old, new));
+    });
+    if let Err(e) = watched {
+        error!("Config changes will need a restart: {e}");
+    }
+
+    // catch up on the status updates ignored while the session was locked
+    let (unlock_handle, unlock_service) = (handle.clone(), service.clone());
+    let followed = session::watch(move || refresh(&unlock_handle, unlock_service.as_ref()));
+    if let Err(e) = followed {
+        info!("Not pausing while the session is locked: {}", describe(&e));
+    }
+
+    spawn_exit_node_monitor(handle.clone());
+    spawn_taildrop_receiver(handle.clone());
+
+    // keep the main thread alive, following the IPN bus for changes
+    IpnWatcher::new(LocalApi::default()).run(|| refresh(&handle, service.as_ref()));
 }
@@ -0,0 +1,165 @@
+//! Follows our logind session, so polling pauses while the screen is locked
+//! and the tray quits before the session or the machine goes down.
+
+use crate::shutdown::{self, Exit};
+use dbus::{
+    arg::prop_cast,
+    blocking::{
+        stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged},
+        SyncConnection,
+    },
+    message::{MatchRule, SignalArgs},
+    Message, Path as ObjectPath,
+};
+use log::{debug, error, info};
+use std::{
+    sync::{Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+use thiserror::Error;
+
+const LOGIND: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER: &str = "org.freedesktop.login1.Manager";
+const SESSION: &str = "org.freedesktop.login1.Session";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+static LOCKED: Mutex<bool> = Mutex::new(false);
+static UNLOCKED: Condvar = Condvar::new();
+
+type Result<T> = std::result::Result<T, SessionError>;
+
+#[derive(Error, Debug)]
+pub enum SessionError {
+    #[error("failed to connect to the system bus")]
+    Connect(#[source] dbus::Error),
+
+    #[error("failed to find our logind session")]
+    Session(#[source] dbus::Error),
+
+    #[error("failed to follow logind signals")]
+    Match(#[source] dbus::Error),
+}
+
+/// Whether the session is locked. Always false if we don't follow the
+/// session at all.
+pub fn is_locked() -> bool {
+    LOCKED.lock().is_ok_and(|locked| *locked)
+}
+
+/// Blocks while the session is locked. Returns right away if we don't
+/// follow the session at all.
+pub fn wait_until_unlocked() {
+    let Ok(mut locked) = LOCKED.lock() else {
+        return;
+    };
+    while *locked {
+        let Ok(guard) = UNLOCKED.wait(locked) else {
+            return;
+        };
+        locked = guard;
+    }
+}
+
+// Returns whether this unlocked the session.
+fn set_locked(locked: bool) -> bool {
+    let Ok(mut current) = LOCKED.lock() else {
+        return false;
+    };
+    if *current == locked {
+        return false;
+    }
+    *current = locked;
+    if locked {
+        info!("Session locked, pausing polling.");
+    } else {
+        info!("Session unlocked, resuming polling.");
+        UNLOCKED.notify_all();
+    }
+    !locked
+}
+
+/// Follows the logind session we run in, calling `on_unlock` whenever it is
+/// unlocked so the tray can catch up on what it missed.
+pub fn watch<F: Fn() + Send + Sync + 'static>(on_unlock: F) -> Result<()> {
+    let conn = SyncConnection::new_system().map_err(SessionError::Connect)?;
+    let manager = conn.with_proxy(LOGIND, MANAGER_PATH, TIMEOUT);
+    // "auto" only works when we were started from within the session, not
+    // e.g. by the systemd user manager
+    let (session,): (ObjectPath<'static>,) = manager
+        .method_call(MANAGER, "GetSession", ("auto",))
+        .or_else(|_| manager.method_call(MANAGER, "GetSessionByPID", (std::process::id(),)))
+        .map_err(SessionError::Session)?;
+
+    let locked = conn
+        .with_proxy(LOGIND, &session, TIMEOUT)
+        .get::<bool>(SESSION, "LockedHint");
+    match locked {
+        Ok(locked) => {
+            set_locked(locked);
+        }
+        Err(e) => debug!("can't tell whether the session is locked: {e}"),
+    }
+
+    // logind's Lock and Unlock signals only ask the screen locker to act,
+    // which may never happen; lockers report whether they are actually
+    // showing through LockedHint, which also covers locking without logind
+    conn.add_match(
+        PropertiesPropertiesChanged::match_rule(Some(&LOGIND.into()), Some(&session))
+            .static_clone(),
+        move |changed: PropertiesPropertiesChanged, _, _: &Message| {
+            if changed.interface_name != SESSION {
+                return true;
+            }
+            if let Some(&locked) = prop_cast::<bool>(&changed.changed_properties, "LockedHint") {
+                if set_locked(locked) {
+                    on_unlock();
+                }
+            }
+            true
+        },
+    )
+    .map_err(SessionError::Match)?;
+
+    conn.add_match(
+        MatchRule::new_signal(MANAGER, "PrepareForShutdown")
+            .with_sender(LOGIND)
+            .with_path(MANAGER_PATH),
+        |(starting,): (bool,), _, _: &Message| {
+            if starting {
+                info!("The system is shutting down.");
+                shutdown::request(Exit::Quit);
+            }
+            true
+        },
+    )
+    .map_err(SessionError::Match)?;
+
+    let ours = session.clone();
+    conn.add_match(
+        MatchRule::new_signal(MANAGER, "SessionRemoved")
+            .with_sender(LOGIND)
+            .with_path(MANAGER_PATH),
+        move |(_, removed): (String, ObjectPath<'static>), _, _: &Message| {
+            if removed == ours {
+                info!("The session has ended.");
+                shutdown::request(Exit::Quit);
+            }
+            true
+        },
+    )
+    .map_err(SessionError::Match)?;
+
+    thread::spawn(move || loop {
+        if let Err(e) = conn.process(Duration::from_secs(1)) {
+            error!("stopped following the logind session: {e}");
+            // don't stay paused with nobody left to tell us about an unlock
+            set_locked(false);
+            return;
+        }
+    });
+
+    info!("Following logind session {session}.");
+    Ok(())
+}
@@ -0,0 +1,210 @@
+//! Quitting cleanly, whether from the menu, `tailray quit`, a signal or the
+//! session ending: the tray is unregistered, commands we are waiting on are
+//! stopped, half-received files are removed and the logs are flushed before
+//! exiting with a status that tells the reasons apart.
+
+use crate::control::socket_path;
+use crate::tray::menu::SysTray;
+use ksni::blocking::Handle;
+use log::{debug, error, info};
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+    low_level::emulate_default_handler,
+};
+use std::{
+    fs, io,
+    path::PathBuf,
+    process::{self, Child, Command, Output, Stdio},
+    sync::{
+        mpsc::{self, Sender},
+        Mutex, OnceLock,
+    },
+    thread,
+};
+
+/// Exit status when the tray fails to start.
+pub const FAILURE: i32 = 1;
+/// Exit status when another tray is already running.
+pub const ALREADY_RUNNING: i32 = 3;
+
+static REQUESTS: OnceLock<Sender<Exit>> = OnceLock::new();
+static IN_FLIGHT: Mutex<InFlight> = Mutex::new(InFlight {
+    stopping: false,
+    next_id: 0,
+    operations: Vec::new(),
+});
+
+/// Why the tray is quitting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// From the menu, `tailray quit`, or because the session or the machine
+    /// is going down. Exits with 0.
+    Quit,
+    /// SIGINT, SIGTERM or SIGHUP. The signal is re-raised once we are done,
+    /// so the exit status is the usual 128 plus the signal number.
+    Signal(i32),
+}
+
+impl Exit {
+    fn code(self) -> i32 {
+        match self {
+            Self::Quit => 0,
+            Self::Signal(signal) => 128 + signal,
+        }
+    }
+}
+
+/// Something that would be left half done if we exited under it.
+#[derive(Debug)]
+enum Operation {
+    Child(u32),
+    PartialFile(PathBuf),
+}
+
+impl Operation {
+    fn cancel(&self) {
+        match self {
+            Self::Child(pid) => {
+                let Ok(pid) = libc::pid_t::try_from(*pid) else {
+                    return;
+                };
+                info!("Stopping process {pid}.");
+                // SAFETY: kill only takes plain integers
+                if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+                    debug!("failed to stop {pid}: {}", io::Error::last_os_error());
+                }
+            }
+            Self::PartialFile(path) => {
+                info!("Removing the partial {}.", path.display());
+                if let Err(e) = fs::remove_file(path) {
+                    debug!("failed to remove {}: {e}", path.display());
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct InFlight {
+    stopping: bool,
+    next_id: u64,
+    operations: Vec<(u64, Operation)>,
+}
+
+/// Keeps an operation tracked until dropped.
+#[must_use]
+pub struct Tracked(u64);
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = IN_FLIGHT.lock() {
+            in_flight.operations.retain(|(id, _)| *id != self.0);
+        }
+    }
+}
+
+fn track(operation: Operation) -> Tracked {
+    let Ok(mut in_flight) = IN_FLIGHT.lock() else {
+        return Tracked(u64::MAX);
+    };
+    // too late, we are already on our way out
+    if in_flight.stopping {
+        operation.cancel();
+    }
+    in_flight.next_id += 1;
+    let id = in_flight.next_id;
+    in_flight.operations.push((id, operation));
+    Tracked(id)
+}
+
+/// Removes the file at `path` if we quit before the returned guard is
+/// dropped, so an interrupted download doesn't look complete.
+pub fn track_partial_file(path: PathBuf) -> Tracked {
+    track(Operation::PartialFile(path))
+}
+
+/// Stops `child` if we quit before the returned guard is dropped, for
+/// commands left running in the background.
+pub fn track_child(child: &Child) -> Tracked {
+    track(Operation::Child(child.id()))
+}
+
+pub trait CommandExt {
+    /// Like [`Command::output`], but stops the command if we quit while
+    /// waiting for it. Unlike `output`, stdout and stderr are inherited
+    /// unless set.
+    fn cancellable_output(&mut self) -> io::Result<Output>;
+}
+
+impl CommandExt for Command {
+    fn cancellable_output(&mut self) -> io::Result<Output> {
+        let child = self.stdin(Stdio::null()).spawn()?;
+        let _tracked = track(Operation::Child(child.id()));
+        child.wait_with_output()
+    }
+}
+
+fn cancel_in_flight() {
+    let Ok(mut in_flight) = IN_FLIGHT.lock() else {
+        return;
+    };
+    in_flight.stopping = true;
+    for (_, operation) in &in_flight.operations {
+        operation.cancel();
+    }
+}
+
+/// Asks the tray to quit. Returns right away, so it is safe to call from
+/// the tray's own callbacks.
+pub fn request(reason: Exit) {
+    match REQUESTS.get() {
+        Some(requests) if requests.send(reason).is_ok() => {}
+        _ => process::exit(reason.code()),
+    }
+}
+
+/// Quits cleanly on the first signal or [`request`], and right away on a
+/// second signal in case the first got stuck.
+pub fn install(handle: Handle<SysTray>) -> io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    let (sender, requests) = mpsc::channel();
+    if REQUESTS.set(sender.clone()).is_err() {
+        return Ok(());
+    }
+
+    thread::spawn(move || {
+        let mut received = false;
+        for signal in signals.forever() {
+            if received {
+                process::exit(Exit::Signal(signal).code());
+            }
+            received = true;
+            let _ = sender.send(Exit::Signal(signal));
+        }
+    });
+
+    thread::spawn(move || {
+        let Ok(reason) = requests.recv() else {
+            return;
+        };
+        match reason {
+            Exit::Quit => info!("Quitting."),
+            Exit::Signal(signal) => info!("Received signal {signal}, quitting."),
+        }
+
+        cancel_in_flight();
+        handle.shutdown().wait();
+        if let Err(e) = fs::remove_file(socket_path()) {
+            error!("failed to remove the control socket: {e}");
+        }
+        log::logger().flush();
+
+        if let Exit::Signal(signal) = reason {
+            let _ = emulate_default_handler(signal);
+        }
+        process::exit(reason.code());
+    });
+
+    Ok(())
+}
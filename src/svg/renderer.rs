@@ -10,12 +10,78 @@ use resvg::{
 
 const SVG_DATA: &str = include_str!("assets/tailscale.svg");
 
+// Badges are drawn over the bottom-right corner of the 26x26 logo.
+const HOURGLASS_BADGE: &str = r##"<circle cx="20" cy="20" r="6" fill="#e5a50a"/><path d="M17.5 16.5h5l-2.5 3.5 2.5 3.5h-5l2.5-3.5z" fill="white"/>"##;
+const WARNING_BADGE: &str = r##"<path d="M20 13.5l6.2 11.5h-12.4z" fill="#e01b24"/><rect x="19.3" y="17" width="1.4" height="4.2" fill="white"/><circle cx="20" cy="23" r="0.8" fill="white"/>"##;
+const FILES_BADGE: &str = r##"<circle cx="20" cy="6" r="6" fill="#2ec27e"/><path d="M20 2.8v5.6M17.4 6l2.6 2.6 2.6-2.6" stroke="white" stroke-width="1.6" stroke-linecap="round" stroke-linejoin="round"/>"##;
+const UNAVAILABLE_BADGE: &str = r##"<circle cx="20" cy="20" r="6" fill="#77767b"/><path d="M17.8 17.8l4.4 4.4M22.2 17.8l-4.4 4.4" stroke="white" stroke-width="1.6" stroke-linecap="round"/>"##;
+const EXIT_NODE_BADGE: &str = r##"<circle cx="20" cy="20" r="6" fill="#3584e4"/><path d="M16.8 20h6M20.3 17.3l2.7 2.7-2.7 2.7" stroke="white" stroke-width="1.6" stroke-linecap="round" stroke-linejoin="round"/>"##;
+
+/// The states Tailray renders a distinct tray icon for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconState {
+    Running,
+    ExitNode,
+    Starting,
+    NeedsLogin,
+    Stopped,
+    /// tailscaled isn't running, or not answering.
+    Unavailable,
+}
+
+impl IconState {
+    /// Name of the matching themed icon, so icon themes can override it.
+    pub fn icon_name(self) -> &'static str {
+        match self {
+            Self::Running => "tailscale-online",
+            Self::ExitNode => "tailscale-exit-node",
+            Self::Starting => "tailscale-starting",
+            Self::NeedsLogin => "tailscale-needs-login",
+            Self::Stopped => "tailscale-offline",
+            Self::Unavailable => "tailscale-unavailable",
+        }
+    }
+
+    fn svg(self) -> String {
+        let (dimmed, badge) = match self {
+            Self::Running => (false, None),
+            Self::ExitNode => (false, Some(EXIT_NODE_BADGE)),
+            Self::Starting => (true, Some(HOURGLASS_BADGE)),
+            Self::NeedsLogin => (true, Some(WARNING_BADGE)),
+            Self::Stopped => (true, None),
+            Self::Unavailable => (true, Some(UNAVAILABLE_BADGE)),
+        };
+
+        let mut svg = if dimmed {
+            // fade the lit dots to match the unlit ones
+            SVG_DATA.replace(r#"<path d="#, r#"<path opacity="0.2" d="#)
+        } else {
+            SVG_DATA.to_string()
+        };
+        if let Some(badge) = badge {
+            svg = svg.replace("</svg>", &format!("{badge}</svg>"));
+        }
+        svg
+    }
+}
+
 pub struct Resvg<'a> {
     options: Options<'a>,
     transform: Transform,
 }
 
 impl Resvg<'_> {
+    fn new() -> Self {
+        let options = Options {
+            fontdb: Arc::new(fontdb::Database::new()),
+            ..Default::default()
+        };
+        Self {
+            options,
+            transform: Transform::default(),
+        }
+    }
+
     pub fn to_icon(&self, svg_str: &str) -> Icon {
         let rtree = Tree::from_str(svg_str, &self.options).unwrap_or_else(|e| {
             panic!("Failed to parse SVG: {e}");
@@ -42,22 +108,21 @@ impl Resvg<'_> {
         }
     }
 
-    pub fn load_icon(enabled: bool) -> Vec<Icon> {
-        let options = Options {
-            fontdb: Arc::new(fontdb::Database::new()),
-            ..Default::default()
-        };
-        let renderer = Self {
-            options,
-            transform: Transform::default(),
-        };
+    pub fn load_icon(state: IconState) -> Vec<Icon> {
+        let renderer = Self::new();
 
-        if enabled {
-            debug!("icon: Tailscale is enabled");
-            vec![renderer.to_icon(SVG_DATA)]
-        } else {
-            debug!("icon: Tailscale is not enabled");
-            vec![renderer.to_icon(&SVG_DATA.replace("1.0", "0.4"))]
-        }
+        debug!("icon: Tailscale is {state:?}");
+        vec![renderer.to_icon(&state.svg())]
+    }
+
+    /// The regular icon for `state`, badged to show received files are
+    /// waiting to be accepted.
+    pub fn load_files_icon(state: IconState) -> Vec<Icon> {
+        let renderer = Self::new();
+
+        let svg = state
+            .svg()
+            .replace("</svg>", &format!("{FILES_BADGE}</svg>"));
+        vec![renderer.to_icon(&svg)]
     }
 }
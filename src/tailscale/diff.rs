@@ -0,0 +1,396 @@
+use crate::tailscale::status::Status;
+use crate::tailscale::types::{BackendState, Machine, StableNodeId};
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+
+/// A single structural difference between two snapshots of [`Status`].
+///
+/// Peers are identified by their node key, which is how tailscaled keys the
+/// `Peer` map, alongside their display name at the time of the change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatusChange {
+    BackendState {
+        from: BackendState,
+        to: BackendState,
+    },
+    SelfIps {
+        from: Vec<IpAddr>,
+        to: Vec<IpAddr>,
+    },
+    ExitNode {
+        from: Option<StableNodeId>,
+        to: Option<StableNodeId>,
+    },
+    Tailnet {
+        from: Option<String>,
+        to: Option<String>,
+    },
+    PeerAdded {
+        key: String,
+        name: String,
+    },
+    PeerRemoved {
+        key: String,
+        name: String,
+    },
+    PeerOnline {
+        key: String,
+        name: String,
+        online: bool,
+    },
+    PeerIps {
+        key: String,
+        name: String,
+        ips: Vec<IpAddr>,
+    },
+    PeerRenamed {
+        key: String,
+        from: String,
+        to: String,
+    },
+    PeerExitNodeOption {
+        key: String,
+        name: String,
+        offered: bool,
+    },
+}
+
+impl Display for StatusChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BackendState { from, to } => write!(f, "backend state {from:?} -> {to:?}"),
+            Self::SelfIps { from, to } => write!(f, "this device's IPs {from:?} -> {to:?}"),
+            Self::ExitNode { from, to } => write!(f, "exit node {from:?} -> {to:?}"),
+            Self::PeerAdded { name, .. } => write!(f, "peer {name} joined"),
+            Self::PeerRemoved { name, .. } => write!(f, "peer {name} left"),
+            Self::PeerOnline { name, online, .. } => write!(
+                f,
+                "peer {name} is {}",
+                if *online { "online" } else { "offline" }
+            ),
+            Self::Tailnet { from, to } => write!(f, "tailnet {from:?} -> {to:?}"),
+            Self::PeerIps { name, ips, .. } => write!(f, "peer {name} IPs changed to {ips:?}"),
+            Self::PeerRenamed { from, to, .. } => write!(f, "peer {from} renamed to {to}"),
+            Self::PeerExitNodeOption { name, offered, .. } => write!(
+                f,
+                "peer {name} {} as an exit node",
+                if *offered {
+                    "is offered"
+                } else {
+                    "is no longer offered"
+                }
+            ),
+        }
+    }
+}
+
+/// Every [`StatusChange`] between two snapshots, in a stable order: self
+/// changes first, then peers sorted by key.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ChangeSet(Vec<StatusChange>);
+
+impl ChangeSet {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, StatusChange> {
+        self.0.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a ChangeSet {
+    type Item = &'a StatusChange;
+    type IntoIter = std::slice::Iter<'a, StatusChange>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Status {
+    /// Computes what changed going from `self` to `new`. Peers' last seen
+    /// times are left out, since they move all the time for offline peers.
+    pub fn diff(&self, new: &Self) -> ChangeSet {
+        let mut changes = Vec::new();
+
+        if self.backend_state() != new.backend_state() {
+            changes.push(StatusChange::BackendState {
+                from: self.backend_state(),
+                to: new.backend_state(),
+            });
+        }
+
+        if self.this_machine.ips != new.this_machine.ips {
+            changes.push(StatusChange::SelfIps {
+                from: self.this_machine.ips.clone(),
+                to: new.this_machine.ips.clone(),
+            });
+        }
+
+        let old_exit = self.exit_node_status.as_ref().map(|e| e.id.clone());
+        let new_exit = new.exit_node_status.as_ref().map(|e| e.id.clone());
+        if old_exit != new_exit {
+            changes.push(StatusChange::ExitNode {
+                from: old_exit,
+                to: new_exit,
+            });
+        }
+
+        let old_tailnet = self.tailnet().map(|tailnet| tailnet.name.clone());
+        let new_tailnet = new.tailnet().map(|tailnet| tailnet.name.clone());
+        if old_tailnet != new_tailnet {
+            changes.push(StatusChange::Tailnet {
+                from: old_tailnet,
+                to: new_tailnet,
+            });
+        }
+
+        let mut keys: Vec<&String> = self.peers.keys().chain(new.peers.keys()).collect();
+        keys.sort();
+        keys.dedup();
+
+        for key in keys {
+            match (self.peers.get(key), new.peers.get(key)) {
+                (None, Some(peer)) => changes.push(StatusChange::PeerAdded {
+                    key: key.clone(),
+                    name: peer.display_name.to_string(),
+                }),
+                (Some(peer), None) => changes.push(StatusChange::PeerRemoved {
+                    key: key.clone(),
+                    name: peer.display_name.to_string(),
+                }),
+                (Some(old), Some(peer)) => Self::diff_peer(key, old, peer, &mut changes),
+                (None, None) => {}
+            }
+        }
+
+        ChangeSet(changes)
+    }
+
+    fn diff_peer(key: &str, old: &Machine, new: &Machine, changes: &mut Vec<StatusChange>) {
+        let (old_name, name) = (old.display_name.to_string(), new.display_name.to_string());
+        if old_name != name {
+            changes.push(StatusChange::PeerRenamed {
+                key: key.to_string(),
+                from: old_name,
+                to: name.clone(),
+            });
+        }
+        if old.exit_node_option != new.exit_node_option {
+            changes.push(StatusChange::PeerExitNodeOption {
+                key: key.to_string(),
+                name: name.clone(),
+                offered: new.exit_node_option,
+            });
+        }
+        if old.online != new.online {
+            changes.push(StatusChange::PeerOnline {
+                key: key.to_string(),
+                name: name.clone(),
+                online: new.online,
+            });
+        }
+        if old.ips != new.ips {
+            changes.push(StatusChange::PeerIps {
+                key: key.to_string(),
+                name,
+                ips: new.ips.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    const SUFFIX: &str = "example.ts.net";
+
+    fn base() -> Value {
+        json!({
+            "Version": "1.0",
+            "TUN": true,
+            "BackendState": "Running",
+            "Self": {"ID": "n1", "DNSName": "me.example.ts.net.", "HostName": "me",
+                     "TailscaleIPs": ["100.64.0.1"], "Online": true,
+                     "ExitNode": false, "ExitNodeOption": false},
+            "MagicDNSSuffix": SUFFIX,
+            "CurrentTailnet": {"Name": "example", "MagicDNSSuffix": SUFFIX,
+                               "MagicDNSEnabled": true},
+            "Peer": {
+                "a": {"ID": "n2", "DNSName": "alpha.example.ts.net.", "HostName": "alpha",
+                      "TailscaleIPs": ["100.64.0.2"], "Online": true,
+                      "ExitNode": false, "ExitNodeOption": false},
+                "b": {"ID": "n3", "DNSName": "beta.example.ts.net.", "HostName": "beta",
+                      "TailscaleIPs": ["100.64.0.3"], "Online": false,
+                      "LastSeen": "2024-05-01T12:30:00Z",
+                      "ExitNode": false, "ExitNodeOption": true}
+            },
+            "User": {}
+        })
+    }
+
+    fn status(json: Value) -> Status {
+        let mut status: Status = serde_json::from_value(json).unwrap();
+        status.this_machine.set_display_name(SUFFIX);
+        for peer in status.peers.values_mut() {
+            peer.set_display_name(SUFFIX);
+        }
+        status
+    }
+
+    // The changes going from `base()` to `base()` edited by `edit`.
+    fn diff(edit: impl FnOnce(&mut Value)) -> Vec<StatusChange> {
+        let mut new = base();
+        edit(&mut new);
+        status(base()).diff(&status(new)).iter().cloned().collect()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn nothing_changes_between_equal_snapshots() {
+        assert!(status(base()).diff(&status(base())).is_empty());
+    }
+
+    #[test]
+    fn ignores_last_seen_times() {
+        assert!(diff(|s| s["Peer"]["b"]["LastSeen"] = json!("2024-05-02T08:00:00Z")).is_empty());
+    }
+
+    #[test]
+    fn reports_backend_state() {
+        assert_eq!(
+            diff(|s| s["BackendState"] = json!("Stopped")),
+            [StatusChange::BackendState {
+                from: BackendState::Running,
+                to: BackendState::Stopped,
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_self_ips() {
+        assert_eq!(
+            diff(|s| s["Self"]["TailscaleIPs"] = json!(["100.64.0.9"])),
+            [StatusChange::SelfIps {
+                from: vec![ip("100.64.0.1")],
+                to: vec![ip("100.64.0.9")],
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_exit_node() {
+        assert_eq!(
+            diff(|s| {
+                s["ExitNodeStatus"] = json!({"ID": "n3", "Online": true, "TailscaleIPs": []});
+            }),
+            [StatusChange::ExitNode {
+                from: None,
+                to: Some(serde_json::from_value(json!("n3")).unwrap()),
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_tailnet() {
+        assert_eq!(
+            diff(|s| s["CurrentTailnet"] = Value::Null),
+            [StatusChange::Tailnet {
+                from: Some("example".into()),
+                to: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_peers_joining_and_leaving() {
+        assert_eq!(
+            diff(|s| {
+                let peers = s["Peer"].as_object_mut().unwrap();
+                let alpha = peers.remove("a").unwrap();
+                peers.insert("c".into(), alpha);
+            }),
+            [
+                StatusChange::PeerRemoved {
+                    key: "a".into(),
+                    name: "alpha".into(),
+                },
+                StatusChange::PeerAdded {
+                    key: "c".into(),
+                    name: "alpha".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_peers_going_online() {
+        assert_eq!(
+            diff(|s| s["Peer"]["b"]["Online"] = json!(true)),
+            [StatusChange::PeerOnline {
+                key: "b".into(),
+                name: "beta".into(),
+                online: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_peer_ips() {
+        assert_eq!(
+            diff(|s| s["Peer"]["a"]["TailscaleIPs"] = json!(["100.64.0.2", "fd7a::2"])),
+            [StatusChange::PeerIps {
+                key: "a".into(),
+                name: "alpha".into(),
+                ips: vec![ip("100.64.0.2"), ip("fd7a::2")],
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_renamed_peers() {
+        assert_eq!(
+            diff(|s| s["Peer"]["a"]["DNSName"] = json!("gamma.example.ts.net.")),
+            [StatusChange::PeerRenamed {
+                key: "a".into(),
+                from: "alpha".into(),
+                to: "gamma".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_exit_node_offers() {
+        assert_eq!(
+            diff(|s| s["Peer"]["b"]["ExitNodeOption"] = json!(false)),
+            [StatusChange::PeerExitNodeOption {
+                key: "b".into(),
+                name: "beta".into(),
+                offered: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn lists_self_changes_before_peers_in_key_order() {
+        let changes = diff(|s| {
+            s["Peer"]["b"]["Online"] = json!(true);
+            s["Peer"]["a"]["Online"] = json!(false);
+            s["BackendState"] = json!("Starting");
+        });
+        let order: Vec<Option<&str>> = changes
+            .iter()
+            .map(|change| match change {
+                StatusChange::PeerOnline { key, .. } => Some(key.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(order, [None, Some("a"), Some("b")]);
+    }
+}
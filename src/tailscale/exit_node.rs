@@ -0,0 +1,68 @@
+use crate::tailscale::localapi::LocalApi;
+use crate::tailscale::peer::ping;
+use crate::tailscale::status::Status;
+use log::{debug, warn};
+use std::{net::IpAddr, thread, time::Duration};
+use thiserror::Error;
+
+type Result<T> = std::result::Result<T, ExitNodeError>;
+
+#[derive(Error, Debug)]
+pub enum ExitNodeError {
+    #[error("no online exit node answered a ping")]
+    NoneReachable,
+}
+
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub name: String,
+    pub ip: IpAddr,
+    pub latency: Duration,
+}
+
+/// Pings every online exit node offered to us, in parallel, and returns the
+/// ones that answered ordered from fastest to slowest.
+pub fn rank(api: &LocalApi, status: &Status) -> Vec<Candidate> {
+    let peers: Vec<_> = status
+        .peers
+        .values()
+        .filter(|peer| peer.exit_node_option && peer.online && !peer.ips.is_empty())
+        .collect();
+
+    let mut candidates: Vec<Candidate> = thread::scope(|scope| {
+        let pings: Vec<_> = peers
+            .iter()
+            .map(|peer| {
+                let ip = peer.ips[0];
+                (peer, scope.spawn(move || ping(api, ip)))
+            })
+            .collect();
+
+        pings
+            .into_iter()
+            .filter_map(|(peer, ping)| match ping.join() {
+                Ok(Ok(latency)) => Some(Candidate {
+                    name: peer.display_name.to_string(),
+                    ip: peer.ips[0],
+                    latency,
+                }),
+                Ok(Err(e)) => {
+                    warn!("skipping exit node {}: {e}", peer.display_name);
+                    None
+                }
+                Err(_) => None,
+            })
+            .collect()
+    });
+
+    candidates.sort_by_key(|candidate| candidate.latency);
+    debug!("exit node ranking: {candidates:?}");
+    candidates
+}
+
+pub fn fastest(api: &LocalApi, status: &Status) -> Result<Candidate> {
+    rank(api, status)
+        .into_iter()
+        .next()
+        .ok_or(ExitNodeError::NoneReachable)
+}
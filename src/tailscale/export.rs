@@ -0,0 +1,173 @@
+//! The JSON printed by `tailray status --json`.
+//!
+//! This is a public interface, so it is kept apart from the types we parse
+//! tailscaled's responses into. Within a schema version fields are only ever
+//! added; renaming or removing one bumps [`SCHEMA_VERSION`].
+
+use crate::tailscale::status::Status;
+use crate::tailscale::types::{BackendState, Machine, PeerKind, StableNodeId};
+use crate::tailscale::utils::{sanitize_hostname, serialize_time};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Debug)]
+pub struct StatusExport {
+    pub schema_version: u32,
+    /// tailscaled's state, e.g. `Running` or `NeedsLogin`.
+    pub backend_state: BackendState,
+    pub connected: bool,
+    pub tailnet: Option<String>,
+    pub this_device: Device,
+    pub exit_node: Option<ExitNode>,
+    /// Peers shown under "My Devices", sorted by name.
+    pub devices: Vec<Device>,
+    /// Peers shown under "Tailscale Services", sorted by name.
+    pub services: Vec<Device>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Device {
+    pub id: StableNodeId,
+    /// The name Tailray shows for the device.
+    pub name: String,
+    pub hostname: String,
+    /// The MagicDNS name, without the trailing dot.
+    pub dns_name: String,
+    pub ips: Vec<IpAddr>,
+    pub online: bool,
+    /// RFC 3339, or `null` if online or never seen.
+    #[serde(serialize_with = "serialize_time")]
+    pub last_seen: Option<SystemTime>,
+    pub exit_node_option: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExitNode {
+    pub id: StableNodeId,
+    pub name: Option<String>,
+    pub online: bool,
+}
+
+impl Device {
+    fn of(machine: &Machine) -> Self {
+        Self {
+            id: machine.id.clone(),
+            name: machine.display_name.to_string(),
+            hostname: sanitize_hostname(&machine.host_name),
+            dns_name: machine.dns_name.trim_end_matches('.').to_string(),
+            ips: machine.ips.clone(),
+            online: machine.online,
+            last_seen: machine.last_seen.filter(|_| !machine.online),
+            exit_node_option: machine.exit_node_option,
+        }
+    }
+}
+
+impl StatusExport {
+    pub fn of(status: &Status) -> Self {
+        let mut peers: Vec<&Machine> = status.peers.values().collect();
+        peers.sort_by_key(|peer| peer.display_name.to_string());
+        let (services, devices): (Vec<&Machine>, Vec<&Machine>) = peers
+            .into_iter()
+            .partition(|peer| matches!(peer.display_name, PeerKind::DNSName(_)));
+
+        let exit_node = status.exit_node_status.as_ref().map(|exit_node| ExitNode {
+            id: exit_node.id.clone(),
+            name: status
+                .peers
+                .values()
+                .find(|peer| peer.id == exit_node.id)
+                .map(|peer| peer.display_name.to_string()),
+            online: exit_node.online,
+        });
+
+        Self {
+            schema_version: SCHEMA_VERSION,
+            backend_state: status.backend_state(),
+            connected: status.is_up(),
+            tailnet: status.tailnet().map(|tailnet| tailnet.name.clone()),
+            this_device: Device::of(&status.this_machine),
+            exit_node,
+            devices: devices.into_iter().map(Device::of).collect(),
+            services: services.into_iter().map(Device::of).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SUFFIX: &str = "example.ts.net";
+
+    #[test]
+    fn export_matches_schema() {
+        let mut status: Status = serde_json::from_value(json!({
+            "Version": "1.0",
+            "TUN": true,
+            "BackendState": "Running",
+            "Self": {"ID": "n1", "DNSName": "me.example.ts.net.", "HostName": "me",
+                     "TailscaleIPs": ["100.64.0.1"], "Online": true,
+                     "ExitNode": false, "ExitNodeOption": false},
+            "MagicDNSSuffix": SUFFIX,
+            "CurrentTailnet": {"Name": "example", "MagicDNSSuffix": SUFFIX,
+                               "MagicDNSEnabled": true},
+            "ExitNodeStatus": {"ID": "n2", "Online": true, "TailscaleIPs": ["100.64.0.2/32"]},
+            "Peer": {
+                "a": {"ID": "n2", "DNSName": "exit.example.ts.net.", "HostName": "exit",
+                      "TailscaleIPs": ["100.64.0.2"], "Online": true,
+                      "LastSeen": "2024-05-01T12:00:00Z",
+                      "ExitNode": true, "ExitNodeOption": true},
+                "b": {"ID": "n3", "DNSName": "laptop.example.ts.net.", "HostName": "laptop",
+                      "TailscaleIPs": ["100.64.0.3", "fd7a:115c:a1e0::3"], "Online": false,
+                      "LastSeen": "2024-05-01T12:00:00Z",
+                      "ExitNode": false, "ExitNodeOption": false},
+                "c": {"ID": "n4", "DNSName": "", "HostName": "Web Server",
+                      "TailscaleIPs": ["100.64.0.4"], "Online": false,
+                      "LastSeen": "0001-01-01T00:00:00Z",
+                      "ExitNode": false, "ExitNodeOption": false}
+            },
+            "User": {}
+        }))
+        .unwrap();
+        status.this_machine.set_display_name(SUFFIX);
+        for peer in status.peers.values_mut() {
+            peer.set_display_name(SUFFIX);
+        }
+
+        assert_eq!(
+            serde_json::to_value(StatusExport::of(&status)).unwrap(),
+            json!({
+                "schema_version": 1,
+                "backend_state": "Running",
+                "connected": true,
+                "tailnet": "example",
+                "this_device": {
+                    "id": "n1", "name": "me", "hostname": "me",
+                    "dns_name": "me.example.ts.net", "ips": ["100.64.0.1"],
+                    "online": true, "last_seen": null, "exit_node_option": false
+                },
+                "exit_node": {"id": "n2", "name": "exit", "online": true},
+                "devices": [
+                    {"id": "n2", "name": "exit", "hostname": "exit",
+                     "dns_name": "exit.example.ts.net", "ips": ["100.64.0.2"],
+                     "online": true, "last_seen": null, "exit_node_option": true},
+                    {"id": "n3", "name": "laptop", "hostname": "laptop",
+                     "dns_name": "laptop.example.ts.net",
+                     "ips": ["100.64.0.3", "fd7a:115c:a1e0::3"],
+                     "online": false, "last_seen": "2024-05-01T12:00:00Z",
+                     "exit_node_option": false}
+                ],
+                "services": [
+                    {"id": "n4", "name": "web-server", "hostname": "web-server",
+                     "dns_name": "", "ips": ["100.64.0.4"],
+                     "online": false, "last_seen": null, "exit_node_option": false}
+                ]
+            })
+        );
+    }
+}
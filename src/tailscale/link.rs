@@ -0,0 +1,52 @@
+use crate::escalation::{tailscale_command, EscalationError};
+use crate::shutdown::CommandExt;
+use log::info;
+use std::{
+    io,
+    process::{ExitStatus, Stdio},
+};
+use thiserror::Error;
+
+type Result<T> = std::result::Result<T, LinkError>;
+
+#[derive(Error, Debug)]
+pub enum LinkError {
+    #[error(transparent)]
+    Escalation(#[from] EscalationError),
+
+    #[error("failed to run tailscale")]
+    Spawn(#[from] io::Error),
+
+    #[error("tailscale {verb} exited with {status}: {stderr}")]
+    Failed {
+        verb: &'static str,
+        status: ExitStatus,
+        stderr: String,
+    },
+}
+
+/// Brings the Tailscale connection up or down.
+pub fn set_link(up: bool) -> Result<()> {
+    let verb = if up { "up" } else { "down" };
+    let output = tailscale_command([verb])?
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .cancellable_output()?;
+
+    info!(
+        "Link {}: [{}]{}",
+        verb,
+        output.status,
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    if !output.status.success() {
+        return Err(LinkError::Failed {
+            verb,
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,416 @@
+use serde::de::DeserializeOwned;
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::OnceLock,
+    time::Duration,
+};
+use thiserror::Error;
+
+pub const DEFAULT_SOCKET_PATH: &str = "/var/run/tailscale/tailscaled.sock";
+
+// tailscaled ignores the host, but HTTP/1.1 requires one to be present.
+const HOST: &str = "local-tailscaled.sock";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Result<T> = std::result::Result<T, LocalApiError>;
+
+#[derive(Error, Debug)]
+pub enum LocalApiError {
+    #[error("failed to connect to tailscaled at {}", path.display())]
+    Connect {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("localapi request failed")]
+    Io(#[from] io::Error),
+
+    #[error("malformed localapi response: {0}")]
+    Malformed(String),
+
+    #[error("localapi returned HTTP {status}: {body}")]
+    Http { status: u16, body: String },
+
+    #[error(transparent)]
+    Deserialize(#[from] serde_json::Error),
+}
+
+impl LocalApiError {
+    /// Whether tailscaled itself is missing, as opposed to answering badly.
+    pub fn is_unreachable(&self) -> bool {
+        matches!(self, Self::Connect { .. } | Self::Io(_))
+    }
+}
+
+/// Minimal HTTP/1.1 client for the tailscaled LocalAPI, spoken over its
+/// Unix socket.
+#[derive(Debug, Clone)]
+pub struct LocalApi {
+    socket_path: PathBuf,
+}
+
+static SOCKET_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+impl Default for LocalApi {
+    fn default() -> Self {
+        let socket_path = SOCKET_OVERRIDE.get().cloned().unwrap_or_else(|| {
+            std::env::var_os("TAILRAY_SOCKET")
+                .map_or_else(|| PathBuf::from(DEFAULT_SOCKET_PATH), PathBuf::from)
+        });
+        Self::new(socket_path)
+    }
+}
+
+impl LocalApi {
+    pub fn new(socket_path: impl Into<PathBuf>) -> Self {
+        Self {
+            socket_path: socket_path.into(),
+        }
+    }
+
+    /// Points every `LocalApi::default()` at `socket_path`, e.g. the one
+    /// given on the command line. Only the first call has any effect.
+    pub fn set_default_socket(socket_path: PathBuf) {
+        let _ = SOCKET_OVERRIDE.set(socket_path);
+    }
+
+    pub fn get(&self, endpoint: &str) -> Result<Vec<u8>> {
+        self.send("GET", endpoint, None, REQUEST_TIMEOUT)
+    }
+
+    pub fn get_json<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
+        let body = self.get(endpoint)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Like [`Self::get_json`], for endpoints that hold the request open for
+    /// up to `wait` until they have something to report.
+    pub fn long_poll_json<T: DeserializeOwned>(&self, endpoint: &str, wait: Duration) -> Result<T> {
+        let body = self.send("GET", endpoint, None, wait + REQUEST_TIMEOUT)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    pub fn post(&self, endpoint: &str, body: &[u8]) -> Result<Vec<u8>> {
+        self.send("POST", endpoint, Some(body), REQUEST_TIMEOUT)
+    }
+
+    pub fn delete(&self, endpoint: &str) -> Result<()> {
+        self.send("DELETE", endpoint, None, REQUEST_TIMEOUT)?;
+        Ok(())
+    }
+
+    pub fn post_json<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
+        let body = self.post(endpoint, &[])?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Opens a long-lived request and hands back the response body as it
+    /// arrives, for streaming endpoints like `watch-ipn-bus`.
+    pub fn stream(&self, endpoint: &str) -> Result<BufReader<Body>> {
+        let response = self.request("GET", endpoint, None, None)?;
+        Ok(BufReader::new(response.into_success()?))
+    }
+
+    /// Like [`Self::stream`], but gives up once the body stalls for longer
+    /// than the request timeout, for transfers that should keep flowing.
+    pub fn download(&self, endpoint: &str) -> Result<BufReader<Body>> {
+        let response = self.request("GET", endpoint, None, Some(REQUEST_TIMEOUT))?;
+        Ok(BufReader::new(response.into_success()?))
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        endpoint: &str,
+        body: Option<&[u8]>,
+        read_timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        let response = self.request(method, endpoint, body, Some(read_timeout))?;
+        let mut body = response.into_success()?;
+        let mut buf = Vec::new();
+        body.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        endpoint: &str,
+        body: Option<&[u8]>,
+        read_timeout: Option<Duration>,
+    ) -> Result<Response> {
+        let mut stream =
+            UnixStream::connect(&self.socket_path).map_err(|source| LocalApiError::Connect {
+                path: self.socket_path.clone(),
+                source,
+            })?;
+        stream.set_read_timeout(read_timeout)?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+        let body = body.unwrap_or_default();
+        let head = format!(
+            "{method} /localapi/v0/{endpoint} HTTP/1.1\r\n\
+             Host: {HOST}\r\n\
+             Sec-Tailscale: localapi\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(body)?;
+        stream.flush()?;
+
+        Response::read(BufReader::new(stream))
+    }
+}
+
+struct Response {
+    status: u16,
+    body: Body,
+}
+
+impl Response {
+    fn read(mut reader: BufReader<UnixStream>) -> Result<Self> {
+        let status_line = read_line(&mut reader)?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| LocalApiError::Malformed(format!("bad status line {status_line:?}")))?;
+
+        let mut content_length = None;
+        let mut chunked = false;
+        loop {
+            let line = read_line(&mut reader)?;
+            if line.is_empty() {
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                return Err(LocalApiError::Malformed(format!("bad header {line:?}")));
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = Some(value.parse().map_err(|_| {
+                    LocalApiError::Malformed(format!("bad content length {value:?}"))
+                })?);
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            }
+        }
+
+        let body = if chunked {
+            Body::Chunked {
+                reader,
+                remaining: 0,
+                done: false,
+            }
+        } else if let Some(len) = content_length {
+            Body::Sized(reader.take(len))
+        } else {
+            Body::Eof(reader)
+        };
+
+        Ok(Self { status, body })
+    }
+
+    fn into_success(self) -> Result<Body> {
+        if (200..300).contains(&self.status) {
+            return Ok(self.body);
+        }
+
+        let mut body = String::new();
+        let mut reader = self.body;
+        // The error body is only informational, so a failed read is not fatal.
+        let _ = reader.read_to_string(&mut body);
+        Err(LocalApiError::Http {
+            status: self.status,
+            body: body.trim().to_string(),
+        })
+    }
+}
+
+/// Response body of a LocalAPI request, with chunked transfer encoding
+/// already decoded.
+pub enum Body {
+    Sized(io::Take<BufReader<UnixStream>>),
+    Chunked {
+        reader: BufReader<UnixStream>,
+        remaining: u64,
+        done: bool,
+    },
+    Eof(BufReader<UnixStream>),
+}
+
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Sized(reader) => reader.read(buf),
+            Self::Eof(reader) => reader.read(buf),
+            Self::Chunked {
+                reader,
+                remaining,
+                done,
+            } => {
+                if *done || buf.is_empty() {
+                    return Ok(0);
+                }
+                if *remaining == 0 {
+                    let size_line = read_line(reader).map_err(into_io)?;
+                    let size = size_line.split(';').next().unwrap_or_default().trim();
+                    *remaining = u64::from_str_radix(size, 16).map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("bad chunk size {size_line:?}"),
+                        )
+                    })?;
+                    if *remaining == 0 {
+                        *done = true;
+                        return Ok(0);
+                    }
+                }
+
+                let max = buf
+                    .len()
+                    .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                let read = reader.read(&mut buf[..max])?;
+                if read == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                *remaining -= read as u64;
+                if *remaining == 0 {
+                    // every chunk is terminated by CRLF
+                    read_line(reader).map_err(into_io)?;
+                }
+                Ok(read)
+            }
+        }
+    }
+}
+
+/// Percent-encodes `segment` for use as a single path segment of an
+/// endpoint, e.g. a file name.
+pub fn escape_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(LocalApiError::Malformed(
+            "unexpected end of response".into(),
+        ));
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn into_io(err: LocalApiError) -> io::Error {
+    match err {
+        LocalApiError::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::BufRead, os::unix::net::UnixListener, thread::JoinHandle};
+
+    // Serves a single canned `response` on a fresh socket, handing back the
+    // request line it was sent.
+    fn stub(name: &str, response: &'static [u8]) -> (LocalApi, JoinHandle<String>) {
+        let path = std::env::temp_dir().join(format!(
+            "tailray-localapi-{}-{name}.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let socket = path.clone();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = std::fs::remove_file(socket);
+            let mut reader = BufReader::new(&stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            (&stream).write_all(response).unwrap();
+            request_line.trim_end().to_string()
+        });
+        (LocalApi::new(path), server)
+    }
+
+    #[test]
+    fn reads_content_length_body() {
+        let (api, server) = stub(
+            "sized",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhelloIGNORED",
+        );
+        assert_eq!(api.get("status").unwrap(), b"hello");
+        assert_eq!(server.join().unwrap(), "GET /localapi/v0/status HTTP/1.1");
+    }
+
+    #[test]
+    fn decodes_chunked_body() {
+        let (api, server) = stub(
+            "chunked",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\n{\"a\":\r\n3;ext=1\r\n 1}\r\n0\r\n\r\n",
+        );
+        let value: serde_json::Value = api.get_json("prefs").unwrap();
+        assert_eq!(value, serde_json::json!({ "a": 1 }));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reads_body_until_eof() {
+        let (api, server) = stub("eof", b"HTTP/1.1 200 OK\r\n\r\nall of it");
+        assert_eq!(api.post("files/", b"").unwrap(), b"all of it");
+        assert_eq!(server.join().unwrap(), "POST /localapi/v0/files/ HTTP/1.1");
+    }
+
+    #[test]
+    fn maps_error_status_to_http_error() {
+        let (api, server) = stub(
+            "forbidden",
+            b"HTTP/1.1 403 Forbidden\r\nContent-Length: 14\r\n\r\naccess denied\n",
+        );
+        match api.get("files/") {
+            Err(LocalApiError::Http { status, body }) => {
+                assert_eq!(status, 403);
+                assert_eq!(body, "access denied");
+            }
+            other => panic!("expected an HTTP error, got {other:?}"),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn missing_socket_is_unreachable() {
+        let api = LocalApi::new("/nonexistent/tailscaled.sock");
+        let err = api.get("status").unwrap_err();
+        assert!(matches!(err, LocalApiError::Connect { .. }));
+        assert!(err.is_unreachable());
+    }
+
+    #[test]
+    fn escapes_path_segments() {
+        assert_eq!(escape_path_segment("a b#%.txt"), "a%20b%23%25.txt");
+    }
+}
@@ -0,0 +1,64 @@
+use crate::escalation::{tailscale_command, EscalationError};
+use crate::shutdown;
+use log::{info, warn};
+use std::{
+    io::{self, BufRead, BufReader},
+    process::Stdio,
+    thread,
+};
+use thiserror::Error;
+
+type Result<T> = std::result::Result<T, LoginError>;
+
+#[derive(Error, Debug)]
+pub enum LoginError {
+    #[error(transparent)]
+    Escalation(#[from] EscalationError),
+
+    #[error("failed to start tailscale")]
+    Spawn(#[from] io::Error),
+
+    #[error("tailscale exited without printing a login URL")]
+    NoUrl,
+
+    #[error("failed to open the login URL in a browser")]
+    Open(#[source] io::Error),
+}
+
+/// Runs `tailscale <args>`, waits for the authentication URL it prints and
+/// opens it in the browser. The command is left running in the background
+/// until the login completes, at which point the IPN bus picks up the change.
+pub fn start_login(args: &[&str]) -> Result<String> {
+    let mut child = tailscale_command(args)?
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // quitting stops a login still waiting on a password prompt or the browser
+    let tracked = shutdown::track_child(&child);
+    let Some(stderr) = child.stderr.take() else {
+        return Err(LoginError::NoUrl);
+    };
+
+    let mut lines = BufReader::new(stderr).lines();
+    let url = lines
+        .by_ref()
+        .map_while(std::result::Result::ok)
+        .map(|line| line.trim().to_string())
+        .find(|line| line.starts_with("https://"));
+
+    let args = args.join(" ");
+    thread::spawn(move || {
+        // keep draining stderr so tailscale never blocks on a full pipe
+        lines.map_while(std::result::Result::ok).for_each(drop);
+        match child.wait() {
+            Ok(status) => info!("tailscale {args} finished: {status}"),
+            Err(e) => warn!("failed to wait for tailscale {args}: {e}"),
+        }
+        drop(tracked);
+    });
+
+    let url = url.ok_or(LoginError::NoUrl)?;
+    info!("Opening login URL {url}");
+    open::that(&url).map_err(LoginError::Open)?;
+    Ok(url)
+}
@@ -1,4 +1,13 @@
+pub mod diff;
+pub mod exit_node;
+pub mod export;
+pub mod link;
+pub mod localapi;
+pub mod login;
 pub mod peer;
+pub mod prefs;
 pub mod status;
+pub mod taildrop;
 pub mod types;
 pub mod utils;
+pub mod watcher;
@@ -1,6 +1,16 @@
 use crate::clipboard::{copy, get};
+use crate::config;
+use crate::tailscale::localapi::{LocalApi, LocalApiError};
+use crate::tailscale::types::PingResult;
 use log::{error, info};
 use notify_rust::Notification;
+use std::{
+    io,
+    net::IpAddr,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
 use thiserror::Error;
 
 type Result<T> = std::result::Result<T, CopyPeerIpError>;
@@ -14,6 +24,25 @@ pub enum CopyPeerIpError {
     Notification(#[from] notify_rust::error::Error),
 }
 
+#[derive(Error, Debug)]
+pub enum PeerActionError {
+    #[error("ping to {ip} failed: {reason}")]
+    Ping { ip: IpAddr, reason: String },
+
+    #[error("failed to launch {program}")]
+    Launch {
+        program: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error(transparent)]
+    LocalApi(#[from] LocalApiError),
+
+    #[error("notification failed")]
+    Notification(#[from] notify_rust::error::Error),
+}
+
 pub fn check_peer_ip(peer_ip: &str) {
     if peer_ip.is_empty() {
         error!("No peer IP.");
@@ -22,26 +51,88 @@ pub fn check_peer_ip(peer_ip: &str) {
     }
 }
 
+/// Copies `text` to the clipboard and confirms it with a notification
+/// titled "Copied {label}".
+pub fn copy_to_clipboard(text: &str, label: &str, notif_body: &str) -> Result<()> {
+    copy(text)?;
+
+    // Get the text back from the clipboard to verify
+    let clip_text = get()?;
+
+    let summary = format!("Copied {label}");
+
+    // log success
+    info!("{summary} {clip_text} to the clipboard");
+
+    // send a notification through dbus
+    if config::get().notifications.clipboard {
+        Notification::new()
+            .summary(&summary)
+            .body(notif_body)
+            .icon("tailscale")
+            .show()?;
+    }
+
+    Ok(())
+}
+
 pub fn copy_peer_ip(peer_ip: &str, notif_body: &str, host: bool) -> Result<()> {
     check_peer_ip(peer_ip);
 
-    copy(peer_ip)?;
+    // Create label for host/peer
+    let label = format!("{} IP address", if host { "host" } else { "peer" });
 
-    // Get IP from clipboard to verify
-    let clip_ip = get()?;
+    copy_to_clipboard(peer_ip, &label, notif_body)
+}
 
-    // Create summary for host/peer
-    let summary = format!("Copied {} IP address", if host { "host" } else { "peer" });
+pub fn ping(api: &LocalApi, ip: IpAddr) -> std::result::Result<Duration, PeerActionError> {
+    let result: PingResult = api.post_json(&format!("ping?ip={ip}&type=disco"))?;
+    if !result.err.is_empty() {
+        return Err(PeerActionError::Ping {
+            ip,
+            reason: result.err,
+        });
+    }
+    Ok(Duration::from_secs_f64(result.latency_seconds))
+}
 
-    // log success
-    info!("{summary} {clip_ip} to the clipboard");
+pub fn ping_peer(name: &str, ip: IpAddr) -> std::result::Result<(), PeerActionError> {
+    let latency = ping(&LocalApi::default(), ip)?;
 
-    // send a notification through dbus
     Notification::new()
-        .summary(&summary)
-        .body(notif_body)
+        .summary(&format!("Pong from {name}"))
+        .body(&format!("{ip} answered in {} ms", latency.as_millis()))
         .icon("tailscale")
         .show()?;
 
     Ok(())
 }
+
+/// Opens an SSH session to `host` in the terminal emulator from the `[ssh]`
+/// config section.
+pub fn ssh_to_peer(host: &str) -> std::result::Result<(), PeerActionError> {
+    let config = config::get();
+    let mut words = config
+        .ssh
+        .terminal
+        .split_whitespace()
+        .chain(config.ssh.command.split_whitespace());
+    let terminal = words.next().unwrap_or("x-terminal-emulator").to_string();
+
+    let mut child = Command::new(&terminal)
+        .args(words)
+        .arg(host)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|source| PeerActionError::Launch {
+            program: terminal,
+            source,
+        })?;
+
+    // reap the terminal once the session is over, rather than leaving a
+    // zombie behind for every session
+    thread::spawn(move || child.wait());
+
+    Ok(())
+}
@@ -0,0 +1,17 @@
+use crate::tailscale::localapi::{LocalApi, LocalApiError};
+use crate::tailscale::types::Prefs;
+use thiserror::Error;
+
+type Result<T> = std::result::Result<T, PrefsError>;
+
+#[derive(Error, Debug)]
+pub enum PrefsError {
+    #[error("failed to fetch tailscale prefs")]
+    LocalApi(#[from] LocalApiError),
+}
+
+impl Prefs {
+    pub fn get(api: &LocalApi) -> Result<Self> {
+        Ok(api.get_json("prefs")?)
+    }
+}
@@ -1,62 +1,97 @@
-use crate::tailscale::types::{BackendState, ExitNodeStatus, Machine, TailnetStatus, User};
+use crate::tailscale::localapi::{LocalApi, LocalApiError};
+use crate::tailscale::types::{
+    BackendState, ExitNodeStatus, LoginProfile, Machine, Prefs, ProfileId, TailnetStatus, User,
+};
+use crate::tailscale::utils::null_as_default;
 use crate::tray::menu::Context;
+use log::error;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, process::Command};
+use std::collections::HashMap;
 use thiserror::Error;
 
 type Result<T> = std::result::Result<T, StatusError>;
 
 #[derive(Error, Debug)]
 pub enum StatusError {
-    #[error("tailscale command failed")]
-    Command(#[from] std::io::Error),
-
-    #[error("failed to decode tailscale command response")]
-    CommandDecode(#[from] std::string::FromUtf8Error),
-
     #[error("failed to fetch tailscale status")]
-    FetchFailed,
+    LocalApi(#[from] LocalApiError),
 
     #[error(transparent)]
     Deserialize(#[from] serde_json::Error),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl StatusError {
+    pub fn is_unreachable(&self) -> bool {
+        matches!(self, Self::LocalApi(e) if e.is_unreachable())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Status {
-    #[serde(rename(deserialize = "Version"))]
+    #[serde(rename = "Version")]
     version: String,
-    #[serde(rename(deserialize = "TUN"))]
+    #[serde(rename = "TUN")]
     tun: bool,
-    #[serde(rename(deserialize = "BackendState"))]
+    #[serde(rename = "BackendState")]
     backend_state: BackendState,
-    #[serde(rename(deserialize = "Self"))]
+    #[serde(rename = "Self")]
     pub this_machine: Machine,
-    #[serde(rename(deserialize = "ExitNodeStatus"))]
+    #[serde(rename = "ExitNodeStatus")]
     pub exit_node_status: Option<ExitNodeStatus>,
-    #[serde(rename(deserialize = "MagicDNSSuffix"))]
+    #[serde(rename = "MagicDNSSuffix")]
     magic_dnssuffix: String,
-    #[serde(rename(deserialize = "CurrentTailnet"))]
-    current_tailnet: TailnetStatus,
-    #[serde(rename(deserialize = "Peer"))]
+    #[serde(rename = "CurrentTailnet")]
+    current_tailnet: Option<TailnetStatus>,
+    #[serde(rename = "Peer", deserialize_with = "null_as_default")]
     pub peers: HashMap<String, Machine>,
-    #[serde(rename(deserialize = "User"))]
+    #[serde(rename = "User", deserialize_with = "null_as_default")]
     user: HashMap<String, User>,
 }
 
 impl Status {
     pub fn get_current() -> Result<Context> {
+        let api = LocalApi::default();
         let status = Self::get()?;
+        let (profiles, current_profile) = Self::get_profiles(&api);
+        let prefs = Prefs::get(&api).unwrap_or_else(|e| {
+            error!("Failed to fetch tailscale prefs: {e}");
+            Prefs::default()
+        });
 
         Ok(Context {
-            ip: status.this_machine.ips[0].to_string(),
+            ip: status
+                .this_machine
+                .ips
+                .first()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
             status,
+            prefs,
+            profiles,
+            current_profile,
         })
     }
 
+    // Profiles only decorate the menu, so failing to list them is not fatal.
+    fn get_profiles(api: &LocalApi) -> (Vec<LoginProfile>, Option<ProfileId>) {
+        let profiles = api
+            .get_json::<Vec<LoginProfile>>("profiles/")
+            .unwrap_or_else(|e| {
+                error!("Failed to list login profiles: {e}");
+                Vec::new()
+            });
+        let current = api
+            .get_json::<LoginProfile>("profiles/current")
+            .map(|profile| profile.id)
+            .ok();
+
+        (profiles, current)
+    }
+
     fn get() -> Result<Status> {
-        let status_json = Self::get_json()?;
-        let mut status: Status = serde_json::from_str(&status_json)?;
-        let dnssuffix = &status.current_tailnet.magic_dnssuffix;
+        let status_json = Self::get_json(&LocalApi::default())?;
+        let mut status: Status = serde_json::from_slice(&status_json)?;
+        let dnssuffix = &status.magic_dnssuffix;
 
         status.this_machine.set_display_name(dnssuffix);
         status
@@ -67,18 +102,38 @@ impl Status {
         Ok(status)
     }
 
-    fn get_json() -> Result<String> {
-        let output = Command::new("tailscale")
-            .arg("status")
-            .arg("--json")
-            .output()?;
-
-        if output.status.success() {
-            let stdout = String::from_utf8(output.stdout)?;
-            Ok(stdout)
-        } else {
-            Err(StatusError::FetchFailed)
+    fn get_json(api: &LocalApi) -> Result<Vec<u8>> {
+        Ok(api.get("status")?)
+    }
+
+    /// Finds this device or a peer by display name, host name or DNS name,
+    /// along with whether it is this device.
+    pub fn find_machine(&self, name: &str) -> Option<(&Machine, bool)> {
+        let matches = |machine: &Machine| {
+            [
+                machine.display_name.to_string().as_str(),
+                machine.host_name.as_str(),
+                machine.dns_name.trim_end_matches('.'),
+            ]
+            .iter()
+            .any(|candidate| candidate.eq_ignore_ascii_case(name))
+        };
+
+        if matches(&self.this_machine) {
+            return Some((&self.this_machine, true));
         }
+        self.peers
+            .values()
+            .find(|peer| matches(peer))
+            .map(|peer| (peer, false))
+    }
+
+    pub fn tailnet(&self) -> Option<&TailnetStatus> {
+        self.current_tailnet.as_ref()
+    }
+
+    pub fn backend_state(&self) -> BackendState {
+        self.backend_state
     }
 
     // TODO: mutex
@@ -0,0 +1,359 @@
+use crate::config;
+use crate::escalation::{should_elevate_perms, tailscale_command, EscalationError};
+use crate::file_chooser::{pick_files, FileChooserError};
+use crate::shutdown::{self, CommandExt};
+use crate::tailscale::localapi::{escape_path_segment, LocalApi, LocalApiError};
+use crate::tailscale::types::WaitingFile;
+use dbus::blocking::Connection;
+use log::{error, info, warn};
+use notify_rust::Notification;
+use std::{
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    sync::{Mutex, PoisonError},
+    thread,
+    time::Duration,
+};
+use thiserror::Error;
+use url::Url;
+
+type Result<T> = std::result::Result<T, SendFileError>;
+
+#[derive(Error, Debug)]
+pub enum ReceiveFileError {
+    #[error(transparent)]
+    LocalApi(#[from] LocalApiError),
+
+    #[error("failed to save {}", path.display())]
+    Save {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum SendFileError {
+    #[error("failed to pick files to send")]
+    FileChooser(#[from] FileChooserError),
+
+    #[error(transparent)]
+    Escalation(#[from] EscalationError),
+
+    #[error("failed to run tailscale file cp")]
+    Spawn(#[from] io::Error),
+
+    #[error("tailscale file cp exited with {status}: {stderr}")]
+    Copy { status: ExitStatus, stderr: String },
+
+    #[error("notification failed")]
+    Notification(#[from] notify_rust::error::Error),
+}
+
+fn file_names(files: &[PathBuf]) -> String {
+    files
+        .iter()
+        .map(|file| {
+            file.file_name()
+                .unwrap_or(file.as_os_str())
+                .to_string_lossy()
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn file_cp(files: &[PathBuf], target: &str) -> Result<()> {
+    let target = format!("{target}:");
+    let mut args: Vec<&OsStr> = vec!["file".as_ref(), "cp".as_ref()];
+    args.extend(files.iter().map(|file| file.as_os_str()));
+    args.push(target.as_ref());
+
+    let output = tailscale_command(args)?
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .cancellable_output()?;
+    if !output.status.success() {
+        return Err(SendFileError::Copy {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+fn notify_send_failed(peer_name: &str, reason: &str) -> Result<()> {
+    Notification::new()
+        .summary(&format!("Failed to send files to {peer_name}"))
+        .body(reason)
+        .icon("dialog-error")
+        .show()?;
+    Ok(())
+}
+
+/// Lets the user pick files and sends them to `target` (a peer's IP or
+/// name) with Taildrop, reporting progress through a notification.
+pub fn send_files(peer_name: &str, target: &str) -> Result<()> {
+    let files = match pick_files(&format!("Send files to {peer_name}")) {
+        Ok(files) => files,
+        Err(e) => {
+            notify_send_failed(peer_name, &e.to_string())?;
+            return Err(e.into());
+        }
+    };
+    if files.is_empty() {
+        info!("no files picked, not sending anything to {peer_name}");
+        return Ok(());
+    }
+
+    let total = files.len();
+    let notify = config::get().notifications.taildrop;
+    let mut progress = if notify {
+        Some(
+            Notification::new()
+                .summary(&format!("Sending {total} file(s) to {peer_name}"))
+                .body(&file_names(&files))
+                .icon("document-send")
+                .show()?,
+        )
+    } else {
+        None
+    };
+
+    // Copy one file at a time so we can report progress, unless every copy
+    // would ask for a password again.
+    let batch_size = if should_elevate_perms() { total } else { 1 };
+    let mut sent = 0;
+    for batch in files.chunks(batch_size) {
+        if let Err(e) = file_cp(batch, target) {
+            if let Some(progress) = progress {
+                progress.close();
+            }
+            notify_send_failed(peer_name, &e.to_string())?;
+            return Err(e);
+        }
+
+        sent += batch.len();
+        info!("sent {} to {peer_name} ({sent}/{total})", file_names(batch));
+        if let Some(progress) = progress.as_mut().filter(|_| sent < total) {
+            progress.body(&format!("Sent {sent} of {total}: {}", file_names(batch)));
+            if let Err(e) = progress.update() {
+                warn!("failed to update the progress notification: {e}");
+            }
+        }
+    }
+
+    if let Some(progress) = progress {
+        progress.close();
+        Notification::new()
+            .summary(&format!("Sent {total} file(s) to {peer_name}"))
+            .body(&file_names(&files))
+            .icon("tailscale")
+            .show()?;
+    }
+
+    Ok(())
+}
+
+// Files being accepted right now, so the menu and the receiver never both
+// download the same one.
+static ACCEPTING: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// A waiting file reserved for us to accept until dropped.
+#[derive(Debug)]
+pub struct Accepting(WaitingFile);
+
+impl Accepting {
+    /// Reserves `file`, unless it is already being accepted.
+    pub fn claim(file: WaitingFile) -> Option<Self> {
+        let mut accepting = ACCEPTING.lock().unwrap_or_else(PoisonError::into_inner);
+        if accepting.contains(&file.name) {
+            return None;
+        }
+        accepting.push(file.name.clone());
+        Some(Self(file))
+    }
+
+    pub fn file(&self) -> &WaitingFile {
+        &self.0
+    }
+}
+
+impl Drop for Accepting {
+    fn drop(&mut self) {
+        ACCEPTING
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|claimed| *claimed != self.0.name);
+    }
+}
+
+/// Whether the file called `name` is being accepted, and so shouldn't be
+/// offered again.
+pub fn is_accepting(name: &str) -> bool {
+    ACCEPTING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .any(|claimed| claimed == name)
+}
+
+/// Lists the files tailscaled is holding for us, waiting up to `wait` for
+/// one to arrive if there are none yet.
+pub fn waiting_files(
+    api: &LocalApi,
+    wait: Duration,
+) -> std::result::Result<Vec<WaitingFile>, ReceiveFileError> {
+    let endpoint = format!("files/?waitsec={}", wait.as_secs());
+    Ok(api.long_poll_json(&endpoint, wait)?)
+}
+
+// Creates "name.ext", or "name (1).ext", "name (2).ext", ... if the name is
+// already taken. Creating rather than checking first means we never
+// overwrite a file that appears in the meantime.
+fn create_unused(dir: &Path, name: &str) -> io::Result<(PathBuf, File)> {
+    let name = Path::new(name);
+    let stem = name
+        .file_stem()
+        .unwrap_or(name.as_os_str())
+        .to_string_lossy();
+    let ext = name
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut path = dir.join(name);
+    let mut n = 1;
+    loop {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                path = dir.join(format!("{stem} ({n}){ext}"));
+                n += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Moves a waiting file out of tailscaled and into `dir`.
+pub fn accept_file(
+    api: &LocalApi,
+    file: &Accepting,
+    dir: &Path,
+) -> std::result::Result<PathBuf, ReceiveFileError> {
+    let file = file.file();
+    let save_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| ReceiveFileError::Save { path, source }
+    };
+
+    fs::create_dir_all(dir).map_err(save_error(dir))?;
+    // tailscaled already strips directories, but never trust a peer's name
+    let name = Path::new(&file.name)
+        .file_name()
+        .map_or_else(|| "taildrop".into(), |name| name.to_string_lossy());
+    let endpoint = format!("files/{}", escape_path_segment(&file.name));
+    let mut body = api.download(&endpoint)?;
+    let (path, mut out) = create_unused(dir, &name).map_err(save_error(&dir.join(&*name)))?;
+    let partial = shutdown::track_partial_file(path.clone());
+    if let Err(source) = io::copy(&mut body, &mut out) {
+        // don't leave a truncated file behind
+        let _ = fs::remove_file(&path);
+        return Err(ReceiveFileError::Save { path, source });
+    }
+    drop(partial);
+
+    // tailscaled would offer the file again, and we would save it twice
+    if let Err(e) = api.delete(&endpoint) {
+        let _ = fs::remove_file(&path);
+        return Err(e.into());
+    }
+    info!("received {} into {}", file.name, path.display());
+    Ok(path)
+}
+
+pub fn reject_file(
+    api: &LocalApi,
+    file: &WaitingFile,
+) -> std::result::Result<(), ReceiveFileError> {
+    api.delete(&format!("files/{}", escape_path_segment(&file.name)))?;
+    info!("rejected {}", file.name);
+    Ok(())
+}
+
+// Asks the file manager to highlight the file, falling back to just opening
+// the folder it is in.
+fn show_in_folder(path: &Path) {
+    // file names from peers can hold anything, so percent-encode them
+    let shown = Url::from_file_path(path)
+        .map_err(|()| format!("{} is not an absolute path", path.display()))
+        .and_then(|uri| {
+            Connection::new_session()
+                .and_then(|conn| {
+                    conn.with_proxy(
+                        "org.freedesktop.FileManager1",
+                        "/org/freedesktop/FileManager1",
+                        Duration::from_secs(5),
+                    )
+                    .method_call::<(), _, _, _>(
+                        "org.freedesktop.FileManager1",
+                        "ShowItems",
+                        (vec![uri.to_string()], ""),
+                    )
+                })
+                .map_err(|e| e.to_string())
+        });
+
+    if let Err(e) = shown {
+        warn!("file manager unavailable, opening the folder instead: {e}");
+        let dir = path.parent().unwrap_or(path);
+        if let Err(e) = open::that(dir) {
+            error!("failed to open {}: {e}", dir.display());
+        }
+    }
+}
+
+/// Announces a received file, offering to open it or show it in its folder.
+pub fn notify_received(path: PathBuf) {
+    if !config::get().notifications.taildrop {
+        return;
+    }
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    // The notification handle can't leave the thread that showed it, and
+    // waiting for the user to pick an action blocks.
+    thread::spawn(move || {
+        let handle = match Notification::new()
+            .summary("Received file")
+            .body(&format!("{name} was saved to {}", path.display()))
+            .icon("document-save")
+            .action("open", "Open")
+            .action("show", "Show in folder")
+            .show()
+        {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!("failed to show notification for {}: {e}", path.display());
+                return;
+            }
+        };
+
+        handle.wait_for_action(|action| match action {
+            "open" => {
+                if let Err(e) = open::that(&path) {
+                    error!("failed to open {}: {e}", path.display());
+                }
+            }
+            "show" => show_in_folder(&path),
+            _ => {}
+        });
+    });
+}
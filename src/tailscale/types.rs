@@ -1,7 +1,11 @@
-use crate::tailscale::utils::{sanitize_hostname, trim_suffix};
+use crate::tailscale::utils::{
+    deserialize_time, empty_as_none, format_ago, null_as_default, sanitize_hostname,
+    serialize_time, trim_suffix,
+};
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 use std::net::IpAddr;
+use std::time::SystemTime;
 
 #[derive(Debug)]
 pub enum PeerKind {
@@ -24,8 +28,9 @@ impl Display for PeerKind {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum BackendState {
+    #[default]
     NoState,
     NeedsLogin,
     NeedsMachineAuth,
@@ -34,8 +39,21 @@ pub enum BackendState {
     Running,
 }
 
+impl BackendState {
+    /// How the state is described to the user.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Running => "Connected",
+            Self::Starting => "Connecting",
+            Self::NeedsLogin => "Logged out",
+            Self::NeedsMachineAuth => "Waiting for admin approval",
+            Self::NoState | Self::Stopped => "Disconnected",
+        }
+    }
+}
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(transparent)]
 pub struct StableNodeId(String);
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -43,31 +61,38 @@ pub struct UserId(u64);
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TailnetStatus {
-    #[serde(rename(deserialize = "Name"))]
+    #[serde(rename = "Name")]
     pub name: String,
-    #[serde(rename(deserialize = "MagicDNSSuffix"))]
+    #[serde(rename = "MagicDNSSuffix")]
     pub magic_dnssuffix: String,
-    #[serde(rename(deserialize = "MagicDNSEnabled"))]
+    #[serde(rename = "MagicDNSEnabled")]
     pub magic_dnsenabled: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Machine {
     #[serde(skip)]
     pub display_name: PeerKind,
-    #[serde(rename(deserialize = "ID"))]
+    #[serde(rename = "ID")]
     pub id: StableNodeId,
-    #[serde(rename(deserialize = "DNSName"))]
+    #[serde(rename = "DNSName")]
     pub dns_name: String,
-    #[serde(rename(deserialize = "HostName"))]
+    #[serde(rename = "HostName")]
     pub host_name: String,
-    #[serde(rename(deserialize = "TailscaleIPs"))]
+    #[serde(rename = "TailscaleIPs", deserialize_with = "null_as_default")]
     pub ips: Vec<IpAddr>,
-    #[serde(rename(deserialize = "Online"))]
+    #[serde(rename = "Online")]
     pub online: bool,
-    #[serde(rename(deserialize = "ExitNode"))]
+    #[serde(
+        rename = "LastSeen",
+        serialize_with = "serialize_time",
+        deserialize_with = "deserialize_time",
+        default
+    )]
+    pub last_seen: Option<SystemTime>,
+    #[serde(rename = "ExitNode")]
     pub exit_node: bool,
-    #[serde(rename(deserialize = "ExitNodeOption"))]
+    #[serde(rename = "ExitNodeOption")]
     pub exit_node_option: bool,
 }
 
@@ -82,28 +107,158 @@ impl Machine {
             PeerKind::HostName(dns_name)
         }
     }
+
+    /// "online", or when the peer was last seen.
+    pub fn presence(&self) -> String {
+        if self.online {
+            return "online".to_string();
+        }
+        self.last_seen.map_or_else(
+            || "offline".to_string(),
+            |time| format!("last seen {}", format_ago(time)),
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct User {
-    #[serde(rename(deserialize = "ID"))]
+    #[serde(rename = "ID")]
     id: UserId,
-    #[serde(rename(deserialize = "LoginName"))]
+    #[serde(rename = "LoginName")]
     login_name: String,
-    #[serde(rename(deserialize = "DisplayName"))]
+    #[serde(rename = "DisplayName")]
     display_name: String,
-    #[serde(rename(deserialize = "ProfilePicURL"))]
+    #[serde(rename = "ProfilePicURL")]
     profile_pic_url: String,
-    #[serde(rename(deserialize = "Roles"))]
+    #[serde(rename = "Roles")]
     roles: Option<Vec<String>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ExitNodeStatus {
-    #[serde(rename(deserialize = "ID"))]
+    #[serde(rename = "ID")]
     pub id: StableNodeId,
-    #[serde(rename(deserialize = "Online"))]
+    #[serde(rename = "Online")]
     pub online: bool,
-    #[serde(rename(deserialize = "TailscaleIPs"))]
+    #[serde(rename = "TailscaleIPs")]
     pub ips: Vec<String>,
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PingResult {
+    #[serde(rename = "Err", default)]
+    pub err: String,
+    #[serde(rename = "LatencySeconds", default)]
+    pub latency_seconds: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProfileId(String);
+
+impl Display for ProfileId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NetworkProfile {
+    #[serde(rename = "MagicDNSName", default)]
+    pub magic_dns_name: String,
+    #[serde(rename = "DomainName", default)]
+    pub domain_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LoginProfile {
+    #[serde(rename = "ID")]
+    pub id: ProfileId,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "NetworkProfile")]
+    pub network_profile: NetworkProfile,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WaitingFile {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Size")]
+    pub size: u64,
+}
+
+/// tailscaled's preferences for this node, as served by the LocalAPI.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Prefs {
+    #[serde(rename = "ControlURL", default)]
+    pub control_url: String,
+    #[serde(rename = "RouteAll", default)]
+    pub route_all: bool,
+    #[serde(rename = "ExitNodeID", deserialize_with = "empty_as_none", default)]
+    pub exit_node_id: Option<StableNodeId>,
+    #[serde(rename = "ExitNodeIP", deserialize_with = "empty_as_none", default)]
+    pub exit_node_ip: Option<IpAddr>,
+    #[serde(rename = "ExitNodeAllowLANAccess", default)]
+    pub exit_node_allow_lan_access: bool,
+    #[serde(rename = "CorpDNS", default)]
+    pub corp_dns: bool,
+    #[serde(rename = "RunSSH", default)]
+    pub run_ssh: bool,
+    #[serde(rename = "WantRunning", default)]
+    pub want_running: bool,
+    #[serde(rename = "LoggedOut", default)]
+    pub logged_out: bool,
+    #[serde(rename = "ShieldsUp", default)]
+    pub shields_up: bool,
+    #[serde(
+        rename = "AdvertiseTags",
+        deserialize_with = "null_as_default",
+        default
+    )]
+    pub advertise_tags: Vec<String>,
+    #[serde(rename = "Hostname", default)]
+    pub hostname: String,
+    #[serde(
+        rename = "AdvertiseRoutes",
+        deserialize_with = "null_as_default",
+        default
+    )]
+    pub advertise_routes: Vec<String>,
+    #[serde(rename = "NoSNAT", default)]
+    pub no_snat: bool,
+    #[serde(rename = "OperatorUser", default)]
+    pub operator_user: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn machine_round_trips_through_json() {
+        let json = r#"{"ID": "n1", "DNSName": "peer.example.ts.net.", "HostName": "peer",
+            "TailscaleIPs": ["100.64.0.2"], "Online": false,
+            "LastSeen": "2024-05-01T12:30:00Z", "ExitNode": false, "ExitNodeOption": true}"#;
+        let machine: Machine = serde_json::from_str(json).unwrap();
+        assert!(machine.last_seen.is_some());
+
+        let again: Machine =
+            serde_json::from_value(serde_json::to_value(&machine).unwrap()).unwrap();
+        assert_eq!(again.last_seen, machine.last_seen);
+        assert_eq!(again.ips, machine.ips);
+        assert_eq!(again.id, machine.id);
+    }
+
+    #[test]
+    fn zero_last_seen_round_trips_as_none() {
+        let machine: Machine = serde_json::from_str(
+            r#"{"ID": "n1", "DNSName": "", "HostName": "me",
+                "TailscaleIPs": null, "Online": true, "LastSeen": "0001-01-01T00:00:00Z",
+                "ExitNode": false, "ExitNodeOption": false}"#,
+        )
+        .unwrap();
+        assert_eq!(machine.last_seen, None);
+        let json = serde_json::to_value(&machine).unwrap();
+        assert_eq!(json["LastSeen"], serde_json::Value::Null);
+    }
+}
@@ -1,10 +1,70 @@
+use crate::tailscale::localapi::LocalApi;
+use crate::tailscale::types::Prefs;
 use log::error;
-use serde_json::Value;
+use serde::{de::IntoDeserializer, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
     collections::HashSet,
-    process::{Command, Stdio},
+    time::{Duration, SystemTime},
 };
 
+/// tailscaled encodes empty maps and slices as `null`, e.g. the peer list
+/// while logged out, so treat those as empty rather than failing.
+pub fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Go leaves unset string fields empty rather than omitting them, e.g. the
+/// exit node ID in prefs while no exit node is in use.
+pub fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) if !value.is_empty() => T::deserialize(value.into_deserializer()).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Parses a Go `time.Time`, mapping its zero value (and anything else we
+/// cannot make sense of) to `None`.
+pub fn deserialize_time<'de, D>(deserializer: D) -> Result<Option<SystemTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let time = Option::<String>::deserialize(deserializer)?;
+    Ok(time.and_then(|time| humantime::parse_rfc3339(&time).ok()))
+}
+
+/// Writes a time the way [`deserialize_time`] reads it, as RFC 3339 or
+/// `null`.
+pub fn serialize_time<S>(time: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    time.map(|time| humantime::format_rfc3339(time).to_string())
+        .serialize(serializer)
+}
+
+/// Formats how long ago `time` was, e.g. "3h ago".
+pub fn format_ago(time: SystemTime) -> String {
+    let elapsed = SystemTime::now()
+        .duration_since(time)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+
+    match elapsed {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", elapsed / 60),
+        3600..86400 => format!("{}h ago", elapsed / 3600),
+        _ => format!("{}d ago", elapsed / 86400),
+    }
+}
+
 pub fn has_suffix(name: &str, suffix: &str) -> bool {
     let name = name.trim_end_matches('.');
     let mut suffix = suffix.trim_end_matches('.');
@@ -59,25 +119,12 @@ pub fn sanitize_hostname(hostname: &str) -> String {
     sanitized
 }
 
-// TODO: maybe properly deserialize the JSON?
 pub fn check_tailscale_operator(user: &str) -> bool {
-    if let Ok(output) = Command::new("tailscale")
-        .arg("debug")
-        .arg("prefs")
-        .stdout(Stdio::piped())
-        .spawn()
-        .unwrap()
-        .wait_with_output()
-    {
-        if output.status.success() {
-            let Ok(prefs) = serde_json::from_slice::<Value>(&output.stdout) else {
-                error!("Failed to parse JSON");
-                return false;
-            };
-            if let Some(operator) = prefs.get("OperatorUser") {
-                return operator.as_str() == Some(user);
-            }
+    match Prefs::get(&LocalApi::default()) {
+        Ok(prefs) => prefs.operator_user == user,
+        Err(e) => {
+            error!("Failed to check the tailscale operator: {e}");
+            false
         }
     }
-    false
 }
@@ -0,0 +1,110 @@
+use crate::config;
+use crate::session;
+use crate::tailscale::localapi::{LocalApi, LocalApiError};
+use log::{debug, info, warn};
+use serde::{de::IgnoredAny, Deserialize};
+use std::{io::BufRead, thread, time::Duration};
+
+// Bits of ipn.NotifyWatchOpt, see tailscale's ipn/backend.go
+const NOTIFY_INITIAL_STATE: u64 = 1 << 1;
+const NOTIFY_INITIAL_NETMAP: u64 = 1 << 3;
+const NOTIFY_NO_PRIVATE_KEYS: u64 = 1 << 4;
+const NOTIFY_INITIAL_HEALTH_STATE: u64 = 1 << 7;
+const NOTIFY_RATE_LIMIT: u64 = 1 << 8;
+
+const WATCH_MASK: u64 = NOTIFY_INITIAL_STATE
+    | NOTIFY_INITIAL_NETMAP
+    | NOTIFY_NO_PRIVATE_KEYS
+    | NOTIFY_INITIAL_HEALTH_STATE
+    | NOTIFY_RATE_LIMIT;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A single message from the IPN bus. Only the parts that tell us the
+/// status needs refreshing are decoded; the payloads themselves are skipped.
+#[derive(Deserialize, Debug)]
+pub struct Notify {
+    #[serde(rename = "ErrMessage")]
+    err_message: Option<String>,
+    #[serde(rename = "State")]
+    state: Option<IgnoredAny>,
+    #[serde(rename = "NetMap")]
+    net_map: Option<IgnoredAny>,
+    #[serde(rename = "Health")]
+    health: Option<IgnoredAny>,
+    #[serde(rename = "Prefs")]
+    prefs: Option<IgnoredAny>,
+}
+
+impl Notify {
+    pub fn changes_status(&self) -> bool {
+        self.state.is_some()
+            || self.net_map.is_some()
+            || self.health.is_some()
+            || self.prefs.is_some()
+    }
+}
+
+/// Follows the tailscaled IPN bus and calls `on_change` whenever the backend
+/// reports a state, netmap, prefs or health change.
+///
+/// When the stream drops, `on_change` is instead called on every reconnect
+/// attempt, so the tray keeps polling with exponential backoff, up to the
+/// configured poll interval between attempts, until the bus is available
+/// again. Neither happens while the session is locked; whoever follows the
+/// session is expected to refresh once it is unlocked.
+pub struct IpnWatcher {
+    api: LocalApi,
+}
+
+impl IpnWatcher {
+    pub fn new(api: LocalApi) -> Self {
+        Self { api }
+    }
+
+    pub fn run<F: FnMut()>(&self, mut on_change: F) -> ! {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            match self.follow(&mut on_change, &mut backoff) {
+                Ok(()) => info!("IPN bus stream closed by tailscaled"),
+                Err(e) => warn!("IPN bus stream failed: {e}"),
+            }
+
+            debug!("polling status, reconnecting to the IPN bus in {backoff:?}");
+            session::wait_until_unlocked();
+            on_change();
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(config::get().poll_interval());
+        }
+    }
+
+    fn follow<F: FnMut()>(
+        &self,
+        on_change: &mut F,
+        backoff: &mut Duration,
+    ) -> Result<(), LocalApiError> {
+        let stream = self
+            .api
+            .stream(&format!("watch-ipn-bus?mask={WATCH_MASK}"))?;
+        info!("watching the IPN bus for changes");
+        *backoff = MIN_BACKOFF;
+
+        for line in stream.lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+
+            let notify: Notify = serde_json::from_str(&line)?;
+            if let Some(err) = &notify.err_message {
+                warn!("tailscaled: {err}");
+            }
+            // the unlock refreshes everything anyway
+            if notify.changes_status() && !session::is_locked() {
+                on_change();
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,100 +1,835 @@
-use crate::pkexec::{get_path, should_elevate_perms};
-use crate::svg::renderer::Resvg;
-use crate::tailscale::peer::copy_peer_ip;
-use crate::tailscale::status::Status;
-use crate::tailscale::types::PeerKind;
+use crate::config::{self, Config, IconTheme, PeerSort};
+use crate::escalation::{root_command, tailscale_command};
+use crate::shutdown::{self, CommandExt, Exit};
+use crate::svg::renderer::{IconState, Resvg};
+use crate::tailscale::diff::ChangeSet;
+use crate::tailscale::exit_node::{self, Candidate};
+use crate::tailscale::link::set_link;
+use crate::tailscale::localapi::LocalApi;
+use crate::tailscale::login::start_login;
+use crate::tailscale::peer::{copy_peer_ip, copy_to_clipboard, ping_peer, ssh_to_peer};
+use crate::tailscale::status::{Status, StatusError};
+use crate::tailscale::taildrop::{
+    accept_file, notify_received, reject_file, send_files, Accepting,
+};
+use crate::tailscale::types::{
+    BackendState, LoginProfile, Machine, PeerKind, Prefs, ProfileId, WaitingFile,
+};
+use crate::tailscale::utils::{check_tailscale_operator, format_ago};
+use crate::tray::utils::in_background;
 
 use ksni::{
-    menu::{StandardItem, SubMenu},
+    menu::{CheckmarkItem, RadioGroup, RadioItem, StandardItem, SubMenu},
     Icon, MenuItem, OfflineReason, ToolTip, Tray,
 };
 
-use log::{error, info};
+use log::{error, info, warn};
 use notify_rust::Notification;
 use std::{
     error::Error,
-    process::{Command, Stdio},
+    process::{Output, Stdio},
+    thread,
+    time::Duration,
 };
+use whoami::username;
 
-#[derive(Debug)]
+const TAILSCALED_UNIT: &str = "tailscaled";
+const DAEMON_START_POLLS: u32 = 10;
+const DAEMON_START_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Default)]
 pub struct Context {
     pub ip: String,
     pub status: Status,
+    pub prefs: Prefs,
+    pub profiles: Vec<LoginProfile>,
+    pub current_profile: Option<ProfileId>,
 }
 
 #[derive(Debug)]
 pub struct SysTray {
     pub ctx: Context,
+    pub auto_exit_node: bool,
+    pub hide_offline_peers: bool,
+    pub taildrop_auto_accept: bool,
+    pub pending_files: Vec<WaitingFile>,
+    /// Why tailscaled can't be reached, while it can't.
+    pub daemon_error: Option<String>,
 }
 
 impl SysTray {
+    /// Picks up toggles changed in the config file, leaving the ones only
+    /// changed from the menu alone.
+    pub fn apply_config(&mut self, old: &Config, new: &Config) {
+        if old.peers.hide_offline != new.peers.hide_offline {
+            self.hide_offline_peers = new.peers.hide_offline;
+        }
+        if old.taildrop.auto_accept != new.taildrop.auto_accept {
+            self.taildrop_auto_accept = new.taildrop.auto_accept;
+        }
+    }
+
     fn enabled(&self) -> bool {
         self.ctx.status.is_up()
     }
 
-    fn update_status(&mut self) -> Result<(), Box<dyn Error>> {
-        self.ctx = Status::get_current()?;
+    fn icon_state(&self) -> IconState {
+        if self.daemon_error.is_some() {
+            return IconState::Unavailable;
+        }
+        match self.ctx.status.backend_state() {
+            BackendState::Running if self.ctx.status.exit_node_status.is_some() => {
+                IconState::ExitNode
+            }
+            BackendState::Running => IconState::Running,
+            BackendState::Starting => IconState::Starting,
+            BackendState::NeedsLogin | BackendState::NeedsMachineAuth => IconState::NeedsLogin,
+            BackendState::NoState | BackendState::Stopped => IconState::Stopped,
+        }
+    }
+
+    pub fn update_status(&mut self) -> Result<(), Box<dyn Error>> {
+        self.set_status(Status::get_current())?;
+        Ok(())
+    }
+
+    /// Shows a freshly fetched status, or that tailscaled can't be reached.
+    /// Returns what changed in the status since the last one we showed, so
+    /// callers only republish it when it did; prefs and profiles are picked
+    /// up either way.
+    pub fn set_status(
+        &mut self,
+        result: Result<Context, StatusError>,
+    ) -> Result<ChangeSet, StatusError> {
+        let ctx = result.inspect_err(|e| {
+            self.note_unreachable(e);
+        })?;
+
+        let recovered = self.daemon_error.take().is_some();
+        if recovered {
+            info!("tailscaled is available again.");
+        }
+        let changes = self.ctx.status.diff(&ctx.status);
+        self.ctx = ctx;
+        Ok(changes)
+    }
+
+    // Remembers why tailscaled is gone if that's what `e` says. The last
+    // known status is kept meanwhile.
+    fn note_unreachable(&mut self, e: &StatusError) {
+        if !e.is_unreachable() {
+            return;
+        }
+        let reason = e
+            .source()
+            .map_or_else(|| e.to_string(), ToString::to_string);
+        if self.daemon_error.is_none() {
+            warn!("tailscaled is unavailable: {reason}");
+        }
+        self.daemon_error = Some(reason);
+    }
+
+    /// Starts tailscaled through systemd, then waits a little for it to
+    /// start answering. Meant to run off the tray's thread.
+    fn start_daemon() -> Result<(), Box<dyn Error>> {
+        let output = root_command("systemctl", ["start", TAILSCALED_UNIT])?
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .cancellable_output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "systemctl start {TAILSCALED_UNIT} exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        info!("Started {TAILSCALED_UNIT}.");
+
+        for _ in 0..DAEMON_START_POLLS {
+            if Status::get_current().is_ok() {
+                break;
+            }
+            thread::sleep(DAEMON_START_POLL_INTERVAL);
+        }
         Ok(())
     }
 
-    fn do_service_link(&mut self, verb: &str) -> Result<(), Box<dyn Error>> {
-        let pkexec_path = get_path();
-        let elevate = should_elevate_perms();
-        let command = if elevate {
-            info!("Elevating permissions for pkexec.");
-            Command::new(pkexec_path)
-                .arg("tailscale")
-                .arg(verb)
-                .stdout(Stdio::piped())
-                .spawn()
+    fn run_tailscale(args: &[&str]) -> Result<Output, Box<dyn Error>> {
+        Ok(tailscale_command(args)?
+            .stdout(Stdio::piped())
+            .cancellable_output()?)
+    }
+
+    pub fn do_service_link(&mut self, up: bool) -> Result<(), Box<dyn Error>> {
+        Self::service_link(up)?;
+        self.update_status()
+    }
+
+    /// Brings the connection up or down without refreshing the tray, for
+    /// use off its thread.
+    pub fn service_link(up: bool) -> Result<(), Box<dyn Error>> {
+        set_link(up)?;
+
+        let (verb, verb_result) = if up {
+            ("up", "online")
         } else {
-            Command::new("tailscale")
-                .arg(verb)
-                .stdout(Stdio::piped())
-                .spawn()
+            ("down", "offline")
         };
+        if config::get().notifications.connection {
+            Notification::new()
+                .summary(format!("Connection {verb}").as_str())
+                .body(format!("Tailscale service {verb_result}").as_str())
+                .icon("info")
+                .show()?;
+        }
 
-        let command = match command {
-            Ok(cmd) => cmd,
-            Err(err) => return Err(Box::new(err)),
-        };
+        Ok(())
+    }
+
+    fn do_set(&mut self, flag: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        Self::set(flag, value)?;
+        self.update_status()
+    }
 
-        let output = command.wait_with_output()?;
+    // `tailscale set` without refreshing the tray, for use off its thread
+    fn set(flag: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        let arg = format!("--{flag}={value}");
+        let output = Self::run_tailscale(&["set", &arg])?;
 
         info!(
-            "Link {}: [{}]{}",
-            &verb,
+            "Set {}: [{}]{}",
+            &arg,
             output.status,
             String::from_utf8_lossy(&output.stdout)
         );
 
-        if output.status.success() {
-            let verb_result = if verb.eq("up") { "online" } else { "offline" };
+        if !output.status.success() {
+            return Err(format!("tailscale set {arg} exited with {}", output.status).into());
+        }
+
+        Ok(())
+    }
+
+    /// Whether we are routing through an exit node that has gone offline.
+    pub fn exit_node_offline(&self) -> bool {
+        self.ctx
+            .status
+            .exit_node_status
+            .as_ref()
+            .is_some_and(|exit_node| !exit_node.online)
+    }
+
+    /// Routes through `to`. This may ask for a password, so it is meant to
+    /// run off the tray's thread, leaving the refresh to the caller.
+    pub fn switch_exit_node(to: &Candidate, summary: &str) -> Result<(), Box<dyn Error>> {
+        Self::set("exit-node", &to.ip.to_string())?;
 
+        if config::get().notifications.exit_node {
             Notification::new()
-                .summary(format!("Connection {verb}").as_str())
-                .body(format!("Tailscale service {verb_result}").as_str())
+                .summary(summary)
+                .body(&format!(
+                    "Now using {} ({} ms)",
+                    to.name,
+                    to.latency.as_millis()
+                ))
                 .icon("info")
                 .show()?;
+        }
+
+        Ok(())
+    }
+
+    // pinging every exit node takes a while, so don't freeze the tray
+    fn use_fastest_exit_node() {
+        in_background("switch to the fastest exit node", || {
+            let ctx = Status::get_current()?;
+            let fastest = exit_node::fastest(&LocalApi::default(), &ctx.status)?;
+            Self::switch_exit_node(&fastest, "Switched to the fastest exit node")
+        });
+    }
 
-            self.update_status()?;
+    /// Routes through the exit node called `name`, or stops using an exit
+    /// node if `name` is empty.
+    pub fn set_exit_node(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        if name.is_empty() {
+            return self.do_set("exit-node", "");
+        }
+
+        let ip = match self.ctx.status.find_machine(name) {
+            Some((peer, false)) if peer.exit_node_option => peer
+                .ips
+                .first()
+                .ok_or_else(|| format!("{name} has no Tailscale IP address"))?
+                .to_string(),
+            Some(_) => return Err(format!("{name} is not offered as an exit node").into()),
+            None => return Err(format!("no peer named {name}").into()),
+        };
+        self.do_set("exit-node", &ip)
+    }
+
+    /// Copies the IP address of this device or the peer called `name` to the
+    /// clipboard, and returns it.
+    pub fn copy_ip(&self, name: &str) -> Result<String, Box<dyn Error>> {
+        let (machine, host) = self
+            .ctx
+            .status
+            .find_machine(name)
+            .ok_or_else(|| format!("no peer named {name}"))?;
+        let ip = machine
+            .ips
+            .first()
+            .ok_or_else(|| format!("{name} has no Tailscale IP address"))?
+            .to_string();
+
+        copy_peer_ip(&ip, &format!("{} ({ip})", machine.display_name), host)?;
+        Ok(ip)
+    }
+
+    fn log_in() -> Result<(), Box<dyn Error>> {
+        let url = start_login(&["login"])?;
+
+        Notification::new()
+            .summary("Log in to Tailscale")
+            .body(&format!("Continue in your browser: {url}"))
+            .icon("info")
+            .show()?;
+
+        Ok(())
+    }
+
+    /// Makes the current user the Tailscale operator, so that changing
+    /// settings no longer needs a password.
+    fn make_operator() -> Result<(), Box<dyn Error>> {
+        let user = username();
+        Self::set("operator", &user)?;
+
+        if !check_tailscale_operator(&user) {
+            return Err(format!("{user} is still not the Tailscale operator").into());
+        }
+
+        Notification::new()
+            .summary("You are now the Tailscale operator")
+            .body("Tailray will no longer ask for your password to change Tailscale settings.")
+            .icon("info")
+            .show()?;
+
+        Ok(())
+    }
+
+    fn switch_profile(id: &ProfileId) -> Result<(), Box<dyn Error>> {
+        let id = id.to_string();
+        let output = Self::run_tailscale(&["switch", &id])?;
+
+        info!(
+            "Switch {}: [{}]{}",
+            &id,
+            output.status,
+            String::from_utf8_lossy(&output.stdout)
+        );
+
+        if !output.status.success() {
+            return Err(format!("tailscale switch {id} exited with {}", output.status).into());
         }
 
         Ok(())
     }
+
+    fn exit_item() -> MenuItem<Self> {
+        StandardItem {
+            label: "Exit Tailray".into(),
+            icon_name: "application-exit".into(),
+            activate: Box::new(|_| shutdown::request(Exit::Quit)),
+            ..Default::default()
+        }
+        .into()
+    }
+
+    // All we can offer while tailscaled is gone.
+    fn unavailable_menu() -> Vec<MenuItem<Self>> {
+        vec![
+            StandardItem {
+                label: "Tailscale daemon unavailable".into(),
+                icon_name: "dialog-warning-symbolic".into(),
+                enabled: false,
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Start tailscaled".into(),
+                icon_name: "system-run-symbolic".into(),
+                activate: Box::new(|_| in_background("start tailscaled", Self::start_daemon)),
+                ..Default::default()
+            }
+            .into(),
+            MenuItem::Separator,
+            Self::exit_item(),
+        ]
+    }
+
+    fn accounts_menu(&self) -> MenuItem<Self> {
+        let ids: Vec<ProfileId> = self.ctx.profiles.iter().map(|p| p.id.clone()).collect();
+        let selected = ids
+            .iter()
+            .position(|id| Some(id) == self.ctx.current_profile.as_ref())
+            .unwrap_or_default();
+
+        let options = self
+            .ctx
+            .profiles
+            .iter()
+            .map(|profile| {
+                let tailnet = &profile.network_profile.domain_name;
+                RadioItem {
+                    label: if tailnet.is_empty() {
+                        profile.name.clone()
+                    } else {
+                        format!("{} ({tailnet})", profile.name)
+                    },
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        SubMenu {
+            label: "Accounts".into(),
+            icon_name: "system-users-symbolic".into(),
+            submenu: vec![
+                RadioGroup {
+                    selected,
+                    select: Box::new(move |this: &mut Self, index| {
+                        if this.ctx.current_profile.as_ref() == Some(&ids[index]) {
+                            return;
+                        }
+                        let id = ids[index].clone();
+                        in_background("switch account", move || Self::switch_profile(&id));
+                    }),
+                    options,
+                }
+                .into(),
+                MenuItem::Separator,
+                StandardItem {
+                    label: "Add account…".into(),
+                    icon_name: "list-add-symbolic".into(),
+                    activate: Box::new(|_| in_background("add an account", Self::log_in)),
+                    ..Default::default()
+                }
+                .into(),
+            ],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn copy_item(label: &str, text: String, peer_name: &str) -> MenuItem<Self> {
+        let what = label.trim_start_matches("Copy ").to_string();
+        let body = format!("{text} ({peer_name})");
+        StandardItem {
+            label: label.into(),
+            icon_name: "edit-copy-symbolic".into(),
+            activate: Box::new(move |_| {
+                if let Err(e) = copy_to_clipboard(&text, &what, &body) {
+                    error!("failed to copy {what}: {e}");
+                }
+            }),
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn peer_menu(peer: &Machine) -> MenuItem<Self> {
+        // tailscaled reports no IPs for peers it knows little about yet
+        let ip = peer.ips.first().copied();
+        let address = ip.map_or_else(|| "no address".to_string(), |ip| ip.to_string());
+        let name = peer.display_name.to_string();
+        let fqdn = peer.dns_name.trim_end_matches('.').to_string();
+        let host = if fqdn.is_empty() {
+            ip.map(|ip| ip.to_string())
+        } else {
+            Some(fqdn.clone())
+        };
+
+        let mut submenu = Vec::new();
+        if let Some(ipv4) = peer.ips.iter().find(|ip| ip.is_ipv4()) {
+            submenu.push(Self::copy_item("Copy IPv4", ipv4.to_string(), &name));
+        }
+        if let Some(ipv6) = peer.ips.iter().find(|ip| ip.is_ipv6()) {
+            submenu.push(Self::copy_item("Copy IPv6", ipv6.to_string(), &name));
+        }
+        if let Some((short_name, _)) = fqdn.split_once('.') {
+            submenu.push(Self::copy_item(
+                "Copy MagicDNS name",
+                short_name.to_string(),
+                &name,
+            ));
+            submenu.push(Self::copy_item(
+                "Copy full domain name",
+                fqdn.clone(),
+                &name,
+            ));
+        }
+
+        let ssh_host = host.clone().unwrap_or_default();
+        let url = format!("http://{}/", host.clone().unwrap_or_default());
+        let ping_name = name.clone();
+        let send_name = name.clone();
+        submenu.extend([
+            MenuItem::Separator,
+            StandardItem {
+                label: "Open SSH session".into(),
+                icon_name: "utilities-terminal-symbolic".into(),
+                enabled: host.is_some(),
+                activate: Box::new(move |_| {
+                    if let Err(e) = ssh_to_peer(&ssh_host) {
+                        error!("failed to open ssh session: {e}");
+                    }
+                }),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Open in browser".into(),
+                icon_name: "web-browser-symbolic".into(),
+                enabled: host.is_some(),
+                activate: Box::new(move |_| {
+                    if let Err(e) = open::that(&url) {
+                        error!("failed to open {url}: {e}");
+                    }
+                }),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Ping".into(),
+                icon_name: "network-transmit-receive-symbolic".into(),
+                enabled: ip.is_some(),
+                activate: Box::new(move |_| {
+                    let Some(ip) = ip else {
+                        return;
+                    };
+                    if let Err(e) = ping_peer(&ping_name, ip) {
+                        error!("failed to ping {ping_name}: {e}");
+                    }
+                }),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Send file…".into(),
+                icon_name: "document-send-symbolic".into(),
+                enabled: peer.online && ip.is_some(),
+                activate: Box::new(move |_| {
+                    let Some(ip) = ip else {
+                        return;
+                    };
+                    // the file chooser blocks until the user is done with it
+                    let name = send_name.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = send_files(&name, &ip.to_string()) {
+                            error!("failed to send files to {name}: {e}");
+                        }
+                    });
+                }),
+                ..Default::default()
+            }
+            .into(),
+        ]);
+
+        let (label, icon_name) = if peer.online {
+            (format!("{address}\t({name})"), "user-available-symbolic")
+        } else {
+            let last_seen = peer
+                .last_seen
+                .map(|time| format!(", last seen {}", format_ago(time)))
+                .unwrap_or_default();
+            (
+                format!("{address}\t({name}{last_seen})"),
+                "user-offline-symbolic",
+            )
+        };
+
+        SubMenu {
+            label,
+            icon_name: icon_name.into(),
+            submenu,
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn accept_files(&mut self, files: Vec<WaitingFile>) {
+        self.pending_files
+            .retain(|pending| !files.contains(pending));
+        // claimed right away, so the receiver doesn't offer them again while
+        // the ones before them download
+        let files: Vec<Accepting> = files.into_iter().filter_map(Accepting::claim).collect();
+        let dir = config::get().taildrop.directory.clone();
+        // downloads can be large, keep them off the tray's thread
+        thread::spawn(move || {
+            let api = LocalApi::default();
+            for file in files {
+                match accept_file(&api, &file, &dir) {
+                    Ok(path) => notify_received(path),
+                    Err(e) => error!("failed to accept {}: {e}", file.file().name),
+                }
+            }
+        });
+    }
+
+    fn reject_files(&mut self, files: Vec<WaitingFile>) {
+        self.pending_files
+            .retain(|pending| !files.contains(pending));
+        let api = LocalApi::default();
+        for file in files {
+            if let Err(e) = reject_file(&api, &file) {
+                error!("failed to reject {}: {e}", file.name);
+            }
+        }
+    }
+
+    fn taildrop_menu(&self) -> MenuItem<Self> {
+        let mut submenu = Vec::new();
+        for file in &self.pending_files {
+            let accept = file.clone();
+            let reject = file.clone();
+            submenu.push(
+                SubMenu {
+                    label: file.name.clone(),
+                    icon_name: "document-save-symbolic".into(),
+                    submenu: vec![
+                        StandardItem {
+                            label: "Accept".into(),
+                            activate: Box::new(move |this: &mut Self| {
+                                this.accept_files(vec![accept.clone()]);
+                            }),
+                            ..Default::default()
+                        }
+                        .into(),
+                        StandardItem {
+                            label: "Reject".into(),
+                            activate: Box::new(move |this: &mut Self| {
+                                this.reject_files(vec![reject.clone()]);
+                            }),
+                            ..Default::default()
+                        }
+                        .into(),
+                    ],
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+
+        let has_pending = !self.pending_files.is_empty();
+        let taildrop_dir = config::get().taildrop.directory.clone();
+        submenu.extend([
+            StandardItem {
+                label: "Accept all".into(),
+                icon_name: "emblem-ok-symbolic".into(),
+                visible: has_pending,
+                activate: Box::new(|this: &mut Self| {
+                    this.accept_files(this.pending_files.clone());
+                }),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Reject all".into(),
+                icon_name: "edit-delete-symbolic".into(),
+                visible: has_pending,
+                activate: Box::new(|this: &mut Self| {
+                    this.reject_files(this.pending_files.clone());
+                }),
+                ..Default::default()
+            }
+            .into(),
+            MenuItem::Separator,
+            CheckmarkItem {
+                label: "Accept incoming files automatically".into(),
+                checked: self.taildrop_auto_accept,
+                activate: Box::new(|this: &mut Self| {
+                    this.taildrop_auto_accept = !this.taildrop_auto_accept;
+                }),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Open received files folder".into(),
+                icon_name: "folder-download-symbolic".into(),
+                activate: Box::new(move |_| {
+                    if let Err(e) = open::that(&taildrop_dir) {
+                        error!("failed to open {}: {e}", taildrop_dir.display());
+                    }
+                }),
+                ..Default::default()
+            }
+            .into(),
+        ]);
+
+        SubMenu {
+            label: if has_pending {
+                format!("Taildrop ({} waiting)", self.pending_files.len())
+            } else {
+                "Taildrop".into()
+            },
+            icon_name: "document-send-symbolic".into(),
+            submenu,
+            ..Default::default()
+        }
+        .into()
+    }
+
+    // toggles a boolean `tailscale set` flag
+    fn pref_item(label: &str, checked: bool, flag: &'static str) -> MenuItem<Self> {
+        CheckmarkItem {
+            label: label.into(),
+            checked,
+            activate: Box::new(move |_| {
+                in_background(format!("set {flag}"), move || {
+                    Self::set(flag, &(!checked).to_string())
+                });
+            }),
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn preferences_menu(&self) -> MenuItem<Self> {
+        let prefs = &self.ctx.prefs;
+        SubMenu {
+            label: "Preferences".into(),
+            icon_name: "preferences-system-symbolic".into(),
+            submenu: vec![
+                Self::pref_item("Accept routes", prefs.route_all, "accept-routes"),
+                Self::pref_item("Use Tailscale DNS", prefs.corp_dns, "accept-dns"),
+                Self::pref_item("Shields up", prefs.shields_up, "shields-up"),
+                Self::pref_item("Run SSH server", prefs.run_ssh, "ssh"),
+                Self::pref_item(
+                    "Allow LAN access with exit node",
+                    prefs.exit_node_allow_lan_access,
+                    "exit-node-allow-lan-access",
+                ),
+                MenuItem::Separator,
+                StandardItem {
+                    label: "Make me operator (stop asking for password)".into(),
+                    icon_name: "dialog-password-symbolic".into(),
+                    // from the prefs we have rather than asking tailscaled on
+                    // every menu rebuild
+                    visible: prefs.operator_user != username() && username() != "root",
+                    activate: Box::new(|_| {
+                        in_background(
+                            format!("make {} the operator", username()),
+                            Self::make_operator,
+                        );
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+            ],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn exit_node_menu(&self) -> MenuItem<Self> {
+        let status = &self.ctx.status;
+        let active = status.exit_node_status.as_ref().map(|e| &e.id);
+
+        let mut exit_nodes: Vec<_> = status
+            .peers
+            .values()
+            .filter(|peer| peer.exit_node_option)
+            .collect();
+        exit_nodes.sort_by_key(|peer| peer.display_name.to_string());
+
+        // the first option always clears the exit node
+        let mut targets = vec![String::new()];
+        let mut options = vec![RadioItem {
+            label: "None".into(),
+            ..Default::default()
+        }];
+        let mut selected = 0;
+        for peer in exit_nodes {
+            // we can only point tailscale at it by IP
+            let Some(ip) = peer.ips.first() else {
+                continue;
+            };
+            if active == Some(&peer.id) {
+                selected = options.len();
+            }
+            targets.push(ip.to_string());
+            options.push(RadioItem {
+                label: format!(
+                    "{}{}",
+                    peer.display_name,
+                    if peer.online { "" } else { " (offline)" }
+                ),
+                ..Default::default()
+            });
+        }
+
+        SubMenu {
+            label: "Exit Node".into(),
+            icon_name: "network-vpn-symbolic".into(),
+            enabled: self.enabled(),
+            submenu: vec![
+                RadioGroup {
+                    selected,
+                    select: Box::new(move |_, index| {
+                        let target = targets[index].clone();
+                        in_background("set the exit node", move || Self::set("exit-node", &target));
+                    }),
+                    options,
+                }
+                .into(),
+                MenuItem::Separator,
+                StandardItem {
+                    label: "Use fastest exit node".into(),
+                    icon_name: "network-transmit-receive-symbolic".into(),
+                    activate: Box::new(|_| Self::use_fastest_exit_node()),
+                    ..Default::default()
+                }
+                .into(),
+                CheckmarkItem {
+                    label: "Switch automatically when offline".into(),
+                    checked: self.auto_exit_node,
+                    activate: Box::new(|this: &mut Self| {
+                        this.auto_exit_node = !this.auto_exit_node;
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+                Self::pref_item(
+                    "Allow LAN access",
+                    self.ctx.prefs.exit_node_allow_lan_access,
+                    "exit-node-allow-lan-access",
+                ),
+            ],
+            ..Default::default()
+        }
+        .into()
+    }
 }
 
 impl Tray for SysTray {
     fn icon_name(&self) -> String {
-        if self.enabled() {
-            "tailscale-online".into()
+        // Themed icons can't show the waiting files badge, leave it to the
+        // pixmap, as we do when asked to always use our own icons. (ksni
+        // hangs when the Status property changes, so we can't ask for
+        // attention that way.)
+        if self.pending_files.is_empty() && config::get().icon_theme == IconTheme::System {
+            self.icon_state().icon_name().into()
         } else {
-            "tailscale-offline".into()
+            String::new()
         }
     }
 
     fn icon_pixmap(&self) -> Vec<Icon> {
-        Resvg::load_icon(self.enabled())
+        if self.pending_files.is_empty() {
+            Resvg::load_icon(self.icon_state())
+        } else {
+            Resvg::load_files_icon(self.icon_state())
+        }
     }
 
     fn id(&self) -> String {
@@ -106,14 +841,16 @@ impl Tray for SysTray {
     }
 
     fn tool_tip(&self) -> ToolTip {
-        let state = if self.enabled() {
-            "Connected"
-        } else {
-            "Disconnected"
-        };
-
+        if let Some(reason) = &self.daemon_error {
+            return ToolTip {
+                title: "Tailscale: daemon unavailable".into(),
+                description: reason.clone(),
+                icon_name: String::default(),
+                icon_pixmap: Vec::default(),
+            };
+        }
         ToolTip {
-            title: format!("Tailscale: {state}"),
+            title: format!("Tailscale: {}", self.ctx.status.backend_state().label()),
             description: String::default(),
             icon_name: String::default(),
             icon_pixmap: Vec::default(),
@@ -121,6 +858,10 @@ impl Tray for SysTray {
     }
 
     fn menu(&self) -> Vec<MenuItem<Self>> {
+        if self.daemon_error.is_some() {
+            return Self::unavailable_menu();
+        }
+
         let my_ip = self.ctx.ip.clone();
 
         let message = format!(
@@ -128,39 +869,62 @@ impl Tray for SysTray {
             self.ctx.status.this_machine.display_name, self.ctx.ip
         );
 
+        let config = config::get();
+        let mut peers: Vec<&Machine> = self
+            .ctx
+            .status
+            .peers
+            .values()
+            .filter(|peer| peer.online || !self.hide_offline_peers)
+            .filter(|peer| !config.peers.hidden.contains(&peer.display_name.to_string()))
+            .collect();
+        match config.peers.sort {
+            PeerSort::Online => {
+                peers.sort_by_cached_key(|peer| (!peer.online, peer.display_name.to_string()));
+            }
+            PeerSort::Name => peers.sort_by_cached_key(|peer| peer.display_name.to_string()),
+        }
+
         let mut my_sub = Vec::new();
         let mut serv_sub = Vec::new();
-        for peer in self.ctx.status.peers.values() {
-            let ip = peer.ips[0].to_string();
-            let name = &peer.display_name;
-            let sub = match name {
+        for peer in peers {
+            let sub = match peer.display_name {
                 PeerKind::DNSName(_) => &mut serv_sub,
                 PeerKind::HostName(_) => &mut my_sub,
             };
+            sub.push(Self::peer_menu(peer));
+        }
+        let backend_state = self.ctx.status.backend_state();
+        let needs_login = backend_state == BackendState::NeedsLogin;
+        let needs_machine_auth = backend_state == BackendState::NeedsMachineAuth;
 
-            let peer_title = format!("{name} ({ip})");
-            let menu = MenuItem::Standard(StandardItem {
-                label: format!("{ip}\t({name})"),
-                activate: Box::new(move |_: &mut Self| {
-                    if let Err(e) = copy_peer_ip(&ip, &peer_title, false) {
-                        error!("failed to copy peer ip: {e}");
+        vec![
+            StandardItem {
+                label: "Log in…".into(),
+                icon_name: "dialog-password-symbolic".into(),
+                visible: needs_login,
+                activate: Box::new(|_| in_background("log in", Self::log_in)),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Waiting for admin approval…".into(),
+                icon_name: "dialog-warning-symbolic".into(),
+                visible: needs_machine_auth,
+                activate: Box::new(|_| {
+                    if let Err(e) = open::that(&config::get().admin_url) {
+                        error!("failed to open admin console: {e}");
                     }
                 }),
                 ..Default::default()
-            });
-            sub.push(menu);
-        }
-        vec![
+            }
+            .into(),
             StandardItem {
                 label: "Connect".into(),
                 icon_name: "network-transmit-receive-symbolic".into(),
                 enabled: !self.enabled(),
-                visible: true,
-                activate: Box::new(|this: &mut Self| {
-                    if let Err(e) = this.do_service_link("up") {
-                        error!("failed to connect: {e}");
-                    }
-                }),
+                visible: !needs_login && !needs_machine_auth,
+                activate: Box::new(|_| in_background("connect", || Self::service_link(true))),
                 ..Default::default()
             }
             .into(),
@@ -169,10 +933,8 @@ impl Tray for SysTray {
                 icon_name: "network-offline-symbolic".into(),
                 enabled: self.enabled(),
                 visible: true,
-                activate: Box::new(|this: &mut Self| {
-                    if let Err(e) = this.do_service_link("down") {
-                        error!("failed to disconnect: {e}");
-                    }
+                activate: Box::new(|_| {
+                    in_background("disconnect", || Self::service_link(false));
                 }),
                 ..Default::default()
             }
@@ -208,18 +970,29 @@ impl Tray for SysTray {
                         ..Default::default()
                     }
                     .into(),
+                    MenuItem::Separator,
+                    CheckmarkItem {
+                        label: "Show offline devices".into(),
+                        checked: !self.hide_offline_peers,
+                        activate: Box::new(|this: &mut Self| {
+                            this.hide_offline_peers = !this.hide_offline_peers;
+                        }),
+                        ..Default::default()
+                    }
+                    .into(),
                 ],
                 ..Default::default()
             }
             .into(),
+            self.exit_node_menu(),
+            self.accounts_menu(),
+            self.taildrop_menu(),
+            self.preferences_menu(),
             StandardItem {
                 label: "Admin Console".into(),
                 icon_name: "applications-system-symbolic".into(),
                 activate: Box::new(|_| {
-                    let admin_url = std::env::var("TAILRAY_ADMIN_URL").unwrap_or_else(|_| {
-                        "https://login.tailscale.com/admin/machines".to_string()
-                    });
-                    if let Err(e) = open::that(admin_url.as_str()) {
+                    if let Err(e) = open::that(&config::get().admin_url) {
                         error!("failed to open admin console: {e}");
                     }
                 }),
@@ -227,13 +1000,7 @@ impl Tray for SysTray {
             }
             .into(),
             MenuItem::Separator,
-            StandardItem {
-                label: "Exit Tailray".into(),
-                icon_name: "application-exit".into(),
-                activate: Box::new(|_| std::process::exit(0)),
-                ..Default::default()
-            }
-            .into(),
+            Self::exit_item(),
         ]
     }
 
@@ -1,18 +1,205 @@
-use crate::tailscale::status::Status;
-use crate::tray::menu::SysTray;
+use crate::config;
+use crate::dbus_service::DbusService;
+use crate::session;
+use crate::tailscale::exit_node;
+use crate::tailscale::localapi::{LocalApi, LocalApiError};
+use crate::tailscale::status::{Status, StatusError};
+use crate::tailscale::taildrop::{
+    accept_file, is_accepting, notify_received, waiting_files, Accepting, ReceiveFileError,
+};
+use crate::tray::menu::{Context, SysTray};
 use ksni::blocking::{Handle, TrayMethods};
-use std::error::Error;
+use log::{debug, error, info, warn};
+use std::{error::Error, fmt::Display, sync::OnceLock, thread, time::Duration};
 
 type TrayServiceError = Box<dyn Error>;
 
-pub fn start_tray_service() -> Result<Handle<SysTray>, TrayServiceError> {
-    let status =
-        Status::get_current().map_err(|e| format!("Failed to update Tailscale status: {e}"))?;
+const TAILDROP_WAIT: Duration = Duration::from_secs(60);
+const TAILDROP_RETRY: Duration = Duration::from_secs(5);
+
+static TRAY: OnceLock<Handle<SysTray>> = OnceLock::new();
 
-    let tray = SysTray { ctx: status };
+/// Starts the tray, showing tailscaled as unavailable if it isn't running
+/// yet rather than failing.
+pub fn start_tray_service() -> Result<Handle<SysTray>, TrayServiceError> {
+    let config = config::get();
+    let mut tray = SysTray {
+        ctx: Context::default(),
+        auto_exit_node: false,
+        hide_offline_peers: config.peers.hide_offline,
+        taildrop_auto_accept: config.taildrop.auto_accept,
+        pending_files: Vec::new(),
+        daemon_error: None,
+    };
+    if let Err(e) = tray.set_status(Status::get_current()) {
+        report_status_error(&e);
+    }
     let handle = tray
         .spawn()
         .map_err(|e| format!("Failed to spawn Tray implementation: {e}"))?;
+    let _ = TRAY.set(handle.clone());
 
     Ok(handle)
 }
+
+/// Fetches the status and shows it in the tray, publishing it on `service`
+/// too when it changed or tailscaled came or went.
+pub fn refresh_status(
+    handle: &Handle<SysTray>,
+    service: Option<&DbusService>,
+) -> Result<(), StatusError> {
+    let result = Status::get_current();
+    let changes = handle.update(|tray| {
+        let was_available = tray.daemon_error.is_none();
+        let changes = tray.set_status(result);
+        let changed = changes.as_ref().is_ok_and(|changes| !changes.is_empty())
+            || tray.daemon_error.is_none() != was_available;
+        if let Some(service) = service.filter(|_| changed) {
+            service.publish(tray);
+        }
+        changes
+    });
+    for change in changes.transpose()?.iter().flatten() {
+        debug!("Tailscale status changed: {change}");
+    }
+    Ok(())
+}
+
+/// Logs why the status couldn't be fetched, unless it's that tailscaled is
+/// unavailable, which the tray shows instead.
+pub fn report_status_error(e: &StatusError) {
+    if !e.is_unreachable() {
+        error!("Failed to update Tailscale status: {e}");
+    }
+}
+
+/// Runs `work` on its own thread, so slow commands and password prompts
+/// don't freeze the tray, then refreshes the tray with the outcome.
+pub fn in_background<W, F>(what: W, work: F)
+where
+    W: Display + Send + 'static,
+    F: FnOnce() -> Result<(), Box<dyn Error>> + Send + 'static,
+{
+    thread::spawn(move || {
+        if let Err(e) = work() {
+            error!("failed to {what}: {e}");
+            return;
+        }
+        if let Some(handle) = TRAY.get() {
+            handle.update(|tray| {
+                if let Err(e) = tray.update_status() {
+                    error!("failed to refresh the status: {e}");
+                }
+            });
+        }
+    });
+}
+
+/// Periodically checks the active exit node while automatic switching is
+/// enabled, and moves to the fastest reachable one once it goes offline.
+pub fn spawn_exit_node_monitor(handle: Handle<SysTray>) {
+    thread::spawn(move || loop {
+        thread::sleep(config::get().poll_interval());
+        session::wait_until_unlocked();
+
+        let Some(stale) = handle.update(|tray| tray.auto_exit_node && tray.exit_node_offline())
+        else {
+            break;
+        };
+        if !stale {
+            continue;
+        }
+
+        info!("exit node went offline, looking for a replacement");
+        let fastest = Status::get_current()
+            .map_err(TrayServiceError::from)
+            .and_then(|ctx| Ok(exit_node::fastest(&LocalApi::default(), &ctx.status)?));
+        let fastest = match fastest {
+            Ok(fastest) => fastest,
+            Err(e) => {
+                error!("failed to find a replacement exit node: {e}");
+                continue;
+            }
+        };
+
+        if let Err(e) = SysTray::switch_exit_node(&fastest, "Exit node went offline") {
+            error!("failed to switch exit node: {e}");
+            continue;
+        }
+        handle.update(|tray| {
+            if let Err(e) = tray.update_status() {
+                error!("failed to refresh after switching exit node: {e}");
+            }
+        });
+    });
+}
+
+/// Waits for incoming Taildrop files, saving them right away when
+/// auto-accept is on and otherwise leaving them pending in the tray.
+pub fn spawn_taildrop_receiver(handle: Handle<SysTray>) {
+    thread::spawn(move || {
+        let api = LocalApi::default();
+        let mut warned = false;
+        loop {
+            session::wait_until_unlocked();
+            let files = match waiting_files(&api, TAILDROP_WAIT) {
+                Ok(files) => files,
+                Err(ReceiveFileError::LocalApi(LocalApiError::Http { status: 403, .. })) => {
+                    if !warned {
+                        warn!("not allowed to receive Taildrop files, Tailray needs to run as the Tailscale operator");
+                        warned = true;
+                    }
+                    thread::sleep(TAILDROP_WAIT);
+                    continue;
+                }
+                // the tray already shows that tailscaled is gone
+                Err(ReceiveFileError::LocalApi(e)) if e.is_unreachable() => {
+                    debug!("can't check for incoming files: {e}");
+                    thread::sleep(config::get().poll_interval());
+                    continue;
+                }
+                Err(e) => {
+                    error!("failed to check for incoming files: {e}");
+                    thread::sleep(TAILDROP_RETRY);
+                    continue;
+                }
+            };
+
+            let Some(auto_accept) = handle.update(|tray| tray.taildrop_auto_accept) else {
+                break;
+            };
+
+            // files accepted from the menu stay waiting until they are in
+            let waiting = !files.is_empty();
+            let (files, receiving): (Vec<_>, Vec<_>) = files
+                .into_iter()
+                .partition(|file| !is_accepting(&file.name));
+
+            if auto_accept {
+                let dir = config::get().taildrop.directory.clone();
+                let mut retry = !receiving.is_empty();
+                for file in files.into_iter().filter_map(Accepting::claim) {
+                    match accept_file(&api, &file, &dir) {
+                        Ok(path) => notify_received(path),
+                        Err(e) => {
+                            error!("failed to accept {}: {e}", file.file().name);
+                            retry = true;
+                        }
+                    }
+                }
+                if !retry {
+                    continue;
+                }
+            } else {
+                handle.update(|tray| tray.pending_files = files);
+            }
+
+            // tailscaled answers right away while files are waiting, so
+            // don't spin until the user or the menu deals with them, or
+            // while accepting them fails
+            if waiting {
+                thread::sleep(TAILDROP_RETRY);
+            }
+        }
+    });
+}